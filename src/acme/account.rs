@@ -0,0 +1,76 @@
+use std::path::Path;
+
+use anyhow::Context;
+use instant_acme::{Account, AccountCredentials, ExternalAccountKey, NewAccount};
+
+use super::AcmeHttpClient;
+use crate::certs::write_private_file;
+
+/// The key id and hmac key that CAs like ZeroSSL and Google hand out to bind new accounts
+/// to an existing account on their side
+#[derive(Debug, Clone)]
+pub struct ExternalAccountBinding {
+    pub key_id: String,
+    pub hmac_key: Vec<u8>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredAccount {
+    directory_url: String,
+    email: String,
+    credentials: AccountCredentials,
+}
+
+/// Restores the ACME account saved at `path`, or registers a new one and saves it there.
+/// A saved account is only reused for the same directory url and email.
+pub async fn load_or_create_account(
+    path: &Path,
+    directory_url: &str,
+    email: &str,
+    eab: Option<&ExternalAccountBinding>,
+    http: &AcmeHttpClient,
+) -> Result<Account, anyhow::Error> {
+    if let Ok(content) = tokio::fs::read(path).await {
+        match serde_json::from_slice::<StoredAccount>(&content) {
+            Ok(stored) if stored.directory_url == directory_url && stored.email == email => {
+                tracing::info!("acme restoring account from {}", path.display());
+                let account =
+                    Account::from_credentials_and_http(stored.credentials, Box::new(http.clone()))
+                        .await?;
+                return Ok(account);
+            }
+            Ok(_) => {
+                tracing::info!("acme saved account is for another directory or email");
+            }
+            Err(err) => {
+                tracing::warn!("acme fail to read saved account: {err}");
+            }
+        }
+    }
+
+    tracing::info!("acme registering new account at {directory_url}");
+    let contact = format!("mailto:{email}");
+    let eab = eab.map(|eab| ExternalAccountKey::new(eab.key_id.to_string(), &eab.hmac_key));
+    let (account, credentials) = Account::create_with_http(
+        &NewAccount {
+            contact: &[&contact],
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        directory_url,
+        eab.as_ref(),
+        Box::new(http.clone()),
+    )
+    .await
+    .context("fail to register acme account")?;
+
+    let stored = StoredAccount {
+        directory_url: directory_url.to_string(),
+        email: email.to_string(),
+        credentials,
+    };
+    write_private_file(path, &serde_json::to_vec_pretty(&stored)?).await?;
+    tracing::info!("acme saved account to {}", path.display());
+
+    Ok(account)
+}
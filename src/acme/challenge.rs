@@ -0,0 +1,90 @@
+use std::{sync::Arc, time::Duration};
+
+use instant_acme::{ChallengeType, KeyAuthorization};
+
+use super::{DnsProvider, Http01Challenges};
+
+/// How the ownership of the domains is proven to the ACME server
+#[derive(Clone)]
+pub enum ChallengeSolver {
+    /// Answers `/.well-known/acme-challenge/<token>` on port 80
+    Http01(Http01Challenges),
+    /// Publishes `_acme-challenge.<domain>` TXT records, also allows wildcard domains
+    Dns01 {
+        provider: Arc<dyn DnsProvider>,
+        propagation_delay: Duration,
+    },
+}
+
+/// A challenge response that was put in place, and has to be removed afterwards
+pub enum PresentedChallenge {
+    Http01 { token: String },
+    Dns01 { fqdn: String, value: String },
+}
+
+impl ChallengeSolver {
+    pub fn challenge_type(&self) -> ChallengeType {
+        match self {
+            ChallengeSolver::Http01(_) => ChallengeType::Http01,
+            ChallengeSolver::Dns01 { .. } => ChallengeType::Dns01,
+        }
+    }
+
+    pub fn supports_wildcards(&self) -> bool {
+        matches!(self, ChallengeSolver::Dns01 { .. })
+    }
+
+    pub async fn present(
+        &self,
+        domain: &str,
+        token: &str,
+        key_authorization: &KeyAuthorization,
+    ) -> Result<PresentedChallenge, anyhow::Error> {
+        match self {
+            ChallengeSolver::Http01(challenges) => {
+                challenges.insert(token, key_authorization.as_str());
+                Ok(PresentedChallenge::Http01 {
+                    token: token.to_string(),
+                })
+            }
+            ChallengeSolver::Dns01 { provider, .. } => {
+                let domain = domain.trim_start_matches("*.").trim_end_matches('.');
+                let fqdn = format!("_acme-challenge.{domain}.");
+                let value = key_authorization.dns_value();
+
+                tracing::info!("acme creating dns-01 record {fqdn}");
+                provider.create_txt_record(&fqdn, &value).await?;
+                Ok(PresentedChallenge::Dns01 { fqdn, value })
+            }
+        }
+    }
+
+    /// Waits until the presented challenges can be seen by the ACME server
+    pub async fn wait_for_propagation(&self) {
+        if let ChallengeSolver::Dns01 {
+            propagation_delay, ..
+        } = self
+        {
+            tracing::info!("acme waiting {propagation_delay:?} for dns-01 records to propagate");
+            tokio::time::sleep(*propagation_delay).await;
+        }
+    }
+
+    pub async fn cleanup(&self, presented: &PresentedChallenge) {
+        match (self, presented) {
+            (ChallengeSolver::Http01(challenges), PresentedChallenge::Http01 { token }) => {
+                challenges.remove(token);
+            }
+            (
+                ChallengeSolver::Dns01 { provider, .. },
+                PresentedChallenge::Dns01 { fqdn, value },
+            ) => {
+                tracing::info!("acme deleting dns-01 record {fqdn}");
+                if let Err(err) = provider.delete_txt_record(fqdn, value).await {
+                    tracing::warn!("acme deleting dns-01 record {fqdn}. ERROR: {err:#}");
+                }
+            }
+            _ => {}
+        }
+    }
+}
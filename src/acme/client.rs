@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use instant_acme::{Account, AuthorizationStatus, Identifier, NewOrder, Order, OrderStatus};
+use rcgen::{CertificateParams, KeyPair};
+
+use super::{AcmeHttpClient, ChallengeSolver, PresentedChallenge};
+
+const POLL_ATTEMPTS: usize = 10;
+const POLL_INITIAL_DELAY: Duration = Duration::from_millis(500);
+const POLL_MAX_DELAY: Duration = Duration::from_secs(10);
+
+pub struct IssuedCertificate {
+    pub cert_chain_pem: String,
+    pub private_key_pem: String,
+}
+
+pub struct AcmeClient {
+    account: Account,
+    http: AcmeHttpClient,
+    preferred_chain: Option<String>,
+}
+
+impl AcmeClient {
+    /// `http` must be the client the account was created with. When the CA offers several
+    /// chains, the one whose topmost certificate is issued by `preferred_chain` is used.
+    pub fn new(account: Account, http: AcmeHttpClient, preferred_chain: Option<String>) -> Self {
+        Self {
+            account,
+            http,
+            preferred_chain,
+        }
+    }
+
+    /// Runs a complete order for the domains, answering the challenges through `solver`,
+    /// and certifies `key_pair`
+    pub async fn obtain_certificate(
+        &self,
+        domains: &[String],
+        solver: &ChallengeSolver,
+        key_pair: &KeyPair,
+    ) -> Result<IssuedCertificate, anyhow::Error> {
+        if !solver.supports_wildcards() {
+            if let Some(domain) = domains.iter().find(|d| d.starts_with("*.")) {
+                bail!("wildcard domain {domain} requires the dns-01 challenge");
+            }
+        }
+
+        let identifiers: Vec<Identifier> = domains
+            .iter()
+            .map(|d| Identifier::Dns(d.to_string()))
+            .collect();
+
+        tracing::info!("acme creating order for {domains:?}");
+        let mut order = self
+            .account
+            .new_order(&NewOrder {
+                identifiers: &identifiers,
+            })
+            .await
+            .context("fail to create acme order")?;
+
+        let mut presented = Vec::new();
+        let res = self.authorize(&mut order, solver, &mut presented).await;
+        for challenge in presented.iter() {
+            solver.cleanup(challenge).await;
+        }
+        res?;
+
+        let csr = CertificateParams::new(domains.to_vec())?.serialize_request(key_pair)?;
+
+        tracing::info!("acme finalizing order");
+        order.finalize(csr.der()).await?;
+
+        let mut cert_chain_pem = None;
+        for delay in poll_delays() {
+            tokio::time::sleep(delay).await;
+            cert_chain_pem = order.certificate().await?;
+            if cert_chain_pem.is_some() {
+                break;
+            }
+        }
+        let cert_chain_pem = cert_chain_pem.context("acme certificate was not issued in time")?;
+
+        let cert_chain_pem = match &self.preferred_chain {
+            Some(preferred) => {
+                self.select_chain(order.url(), cert_chain_pem, preferred)
+                    .await?
+            }
+            None => cert_chain_pem,
+        };
+
+        Ok(IssuedCertificate {
+            cert_chain_pem,
+            private_key_pem: key_pair.serialize_pem(),
+        })
+    }
+
+    /// Downloads the alternate chains until one matches, or keeps the default chain
+    async fn select_chain(
+        &self,
+        order_url: &str,
+        default_chain: String,
+        preferred: &str,
+    ) -> Result<String, anyhow::Error> {
+        if chain_root_name(&default_chain).as_deref() == Some(preferred) {
+            return Ok(default_chain);
+        }
+
+        for url in self.http.take_alternates() {
+            self.http.override_certificate_url(&url);
+            let mut order = self.account.order(order_url.to_string()).await?;
+            let Some(chain) = order.certificate().await? else {
+                continue;
+            };
+
+            if chain_root_name(&chain).as_deref() == Some(preferred) {
+                tracing::info!("acme using the alternate chain {url}");
+                return Ok(chain);
+            }
+        }
+
+        tracing::warn!("acme preferred chain {preferred} is not offered, using the default chain");
+        Ok(default_chain)
+    }
+
+    async fn authorize(
+        &self,
+        order: &mut Order,
+        solver: &ChallengeSolver,
+        presented: &mut Vec<PresentedChallenge>,
+    ) -> Result<(), anyhow::Error> {
+        let authorizations = order.authorizations().await?;
+
+        let mut challenge_urls = Vec::new();
+        for authz in authorizations.iter() {
+            let Identifier::Dns(domain) = &authz.identifier;
+            match authz.status {
+                AuthorizationStatus::Pending => {}
+                AuthorizationStatus::Valid => continue,
+                status => bail!("acme authorization for {domain} is {status:?}"),
+            }
+
+            let challenge_type = solver.challenge_type();
+            let challenge = authz
+                .challenges
+                .iter()
+                .find(|c| c.r#type == challenge_type)
+                .with_context(|| format!("no {challenge_type:?} challenge offered for {domain}"))?;
+
+            let key_authorization = order.key_authorization(challenge);
+            presented.push(
+                solver
+                    .present(domain, &challenge.token, &key_authorization)
+                    .await?,
+            );
+            challenge_urls.push(challenge.url.to_string());
+        }
+
+        if !challenge_urls.is_empty() {
+            solver.wait_for_propagation().await;
+        }
+
+        for url in challenge_urls.iter() {
+            order.set_challenge_ready(url).await?;
+        }
+
+        let mut status = OrderStatus::Pending;
+        for delay in poll_delays() {
+            tokio::time::sleep(delay).await;
+            status = order.refresh().await?.status;
+            if status != OrderStatus::Pending {
+                break;
+            }
+        }
+
+        if status == OrderStatus::Pending {
+            bail!("acme order was not validated in time");
+        }
+        if status != OrderStatus::Ready {
+            let error = order.state().error.as_ref().map(|e| e.to_string());
+            bail!("acme order is {status:?}: {}", error.unwrap_or_default());
+        }
+
+        Ok(())
+    }
+}
+
+/// Delays between polls of the acme server, doubling up to a maximum
+fn poll_delays() -> impl Iterator<Item = Duration> {
+    std::iter::successors(Some(POLL_INITIAL_DELAY), |d| {
+        Some((*d * 2).min(POLL_MAX_DELAY))
+    })
+    .take(POLL_ATTEMPTS)
+}
+
+/// Returns the issuer common name of the topmost certificate in the chain, the same name that
+/// certbot's `--preferred-chain` matches
+fn chain_root_name(chain_pem: &str) -> Option<String> {
+    let topmost = x509_parser::pem::Pem::iter_from_buffer(chain_pem.as_bytes())
+        .filter_map(|pem| pem.ok())
+        .last()?;
+    let cert = topmost.parse_x509().ok()?;
+    let name = cert.issuer().iter_common_name().next()?.as_str().ok()?;
+    Some(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+
+    use super::chain_root_name;
+
+    fn ca_params(name: &str) -> CertificateParams {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, name);
+        params
+    }
+
+    #[test]
+    fn test_chain_root_name() {
+        let root_key = KeyPair::generate().unwrap();
+        let root = ca_params("Test Root X1").self_signed(&root_key).unwrap();
+        let intermediate_key = KeyPair::generate().unwrap();
+        let intermediate = ca_params("Test E5")
+            .signed_by(&intermediate_key, &root, &root_key)
+            .unwrap();
+        let leaf_key = KeyPair::generate().unwrap();
+        let leaf = CertificateParams::new(vec!["dns.example.com".to_string()])
+            .unwrap()
+            .signed_by(&leaf_key, &intermediate, &intermediate_key)
+            .unwrap();
+
+        let chain = format!("{}{}", leaf.pem(), intermediate.pem());
+        assert_eq!(chain_root_name(&chain).as_deref(), Some("Test Root X1"));
+        assert_eq!(chain_root_name(&leaf.pem()).as_deref(), Some("Test E5"));
+        assert_eq!(chain_root_name(""), None);
+    }
+}
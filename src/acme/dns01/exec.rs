@@ -0,0 +1,97 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{bail, Context};
+use tokio::process::Command;
+
+use super::DnsProvider;
+
+/// Runs an external hook to manage the TXT records, for dns hosts that have no built-in backend.
+/// The hook is called as `<command> present <fqdn> <value>` and `<command> cleanup <fqdn> <value>`,
+/// and must exit with status 0 on success. A hook still running after `timeout` is killed.
+pub struct ExecProvider {
+    command: PathBuf,
+    timeout: Duration,
+}
+
+impl ExecProvider {
+    pub fn new(command: PathBuf, timeout: Duration) -> Self {
+        Self { command, timeout }
+    }
+
+    async fn run(&self, action: &str, fqdn: &str, value: &str) -> Result<(), anyhow::Error> {
+        let output = Command::new(&self.command)
+            .arg(action)
+            .arg(fqdn)
+            .arg(value)
+            .kill_on_drop(true)
+            .output();
+        let output = tokio::time::timeout(self.timeout, output)
+            .await
+            .with_context(|| {
+                format!(
+                    "dns-01 hook `{} {action}` did not exit within {:?}",
+                    self.command.display(),
+                    self.timeout
+                )
+            })??;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!(
+                "dns-01 hook `{} {action}` exited with {}: {}",
+                self.command.display(),
+                output.status,
+                stderr.trim()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl DnsProvider for ExecProvider {
+    async fn create_txt_record(&self, fqdn: &str, value: &str) -> Result<(), anyhow::Error> {
+        self.run("present", fqdn, value).await
+    }
+
+    async fn delete_txt_record(&self, fqdn: &str, value: &str) -> Result<(), anyhow::Error> {
+        self.run("cleanup", fqdn, value).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, time::Duration};
+
+    use super::{DnsProvider, ExecProvider};
+
+    #[tokio::test]
+    async fn test_hook_timeout() {
+        let hook = std::env::temp_dir().join(format!("{}-dns01-hook.sh", std::process::id()));
+        std::fs::write(
+            &hook,
+            "#!/bin/sh\n[ \"$1\" = present ] || exit 1\nsleep 10\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let provider = ExecProvider::new(hook.clone(), Duration::from_millis(200));
+        let err = provider
+            .create_txt_record("_acme-challenge.example.com", "value")
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("did not exit within"),
+            "{err:#}"
+        );
+
+        let err = provider
+            .delete_txt_record("_acme-challenge.example.com", "value")
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("exited with"), "{err:#}");
+
+        std::fs::remove_file(&hook).unwrap();
+    }
+}
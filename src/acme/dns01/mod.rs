@@ -0,0 +1,15 @@
+mod exec;
+mod rfc2136;
+
+pub use exec::*;
+pub use rfc2136::*;
+
+/// A backend that can publish the TXT records for ACME DNS-01 challenges.
+/// The same name may hold several values at once, e.g. for `example.com` and `*.example.com`,
+/// so implementations should add and remove single values rather than replace the whole set.
+#[async_trait::async_trait]
+pub trait DnsProvider: Send + Sync {
+    async fn create_txt_record(&self, fqdn: &str, value: &str) -> Result<(), anyhow::Error>;
+
+    async fn delete_txt_record(&self, fqdn: &str, value: &str) -> Result<(), anyhow::Error>;
+}
@@ -0,0 +1,311 @@
+use std::{net::SocketAddr, str::FromStr, time::Duration};
+
+use anyhow::{bail, Context};
+use hickory_proto::{
+    op::{update_message, Message, MessageType, MessageVerifier, OpCode, Query, ResponseCode},
+    rr::{
+        dnssec::{rdata::tsig::TsigAlgorithm, tsig::TSigner},
+        rdata::TXT,
+        Name, RData, RecordSet, RecordType,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::DnsProvider;
+
+const TXT_TTL: u32 = 60;
+const TSIG_FUDGE: u16 = 300;
+/// How long the server may take to answer a request, from connecting to reading the response
+const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Publishes TXT records through RFC 2136 dynamic updates, optionally signed with a TSIG key
+pub struct Rfc2136Provider {
+    server: SocketAddr,
+    zone: Option<Name>,
+    signer: Option<TSigner>,
+}
+
+impl Rfc2136Provider {
+    pub fn new(server: SocketAddr, zone: Option<&str>) -> Result<Self, anyhow::Error> {
+        let zone = zone.map(Name::from_str).transpose()?;
+        Ok(Self {
+            server,
+            zone,
+            signer: None,
+        })
+    }
+
+    /// Signs the updates with a TSIG key, `algorithm` is e.g. `hmac-sha256`
+    pub fn with_tsig(
+        mut self,
+        key_name: &str,
+        algorithm: &str,
+        secret: Vec<u8>,
+    ) -> Result<Self, anyhow::Error> {
+        let algorithm = TsigAlgorithm::from_name(Name::from_str(algorithm)?);
+        let signer = TSigner::new(secret, algorithm, Name::from_str(key_name)?, TSIG_FUDGE)?;
+        self.signer = Some(signer);
+        Ok(self)
+    }
+
+    /// Finds the zone to update, either the configured one or the SOA owner the server reports
+    async fn zone_for(&self, name: &Name) -> Result<Name, anyhow::Error> {
+        if let Some(zone) = &self.zone {
+            return Ok(zone.clone());
+        }
+
+        let mut message = Message::new();
+        message
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .add_query(Query::query(name.clone(), RecordType::SOA));
+
+        let response = self.exchange(message, None).await?;
+        if response.response_code() != ResponseCode::NoError {
+            bail!(
+                "dns query for the SOA of {name} failed on {}: {}",
+                self.server,
+                response.response_code()
+            );
+        }
+        response
+            .answers()
+            .iter()
+            .chain(response.name_servers())
+            .find(|r| r.record_type() == RecordType::SOA)
+            .map(|r| r.name().clone())
+            .with_context(|| format!("no SOA found for {name}, set the zone explicitly"))
+    }
+
+    fn txt_rrset(name: &Name, value: &str) -> RecordSet {
+        let mut rrset = RecordSet::with_ttl(name.clone(), RecordType::TXT, TXT_TTL);
+        rrset.add_rdata(RData::TXT(TXT::new(vec![value.to_string()])));
+        rrset
+    }
+
+    /// Sends the update, and checks that a signed update is answered with a response signed by
+    /// the same key
+    async fn update(&self, mut message: Message) -> Result<(), anyhow::Error> {
+        let verifier = match &self.signer {
+            Some(signer) => {
+                let now = chrono::Utc::now().timestamp() as u32;
+                message.finalize(signer, now)?
+            }
+            None => None,
+        };
+
+        let response = self.exchange(message, verifier).await?;
+        if response.response_code() != ResponseCode::NoError {
+            bail!(
+                "dns update rejected by {}: {}",
+                self.server,
+                response.response_code()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Sends the message over tcp, which has no size limit and is always available for updates.
+    /// The response is checked by the `verifier` of a signed message.
+    async fn exchange(
+        &self,
+        message: Message,
+        verifier: Option<MessageVerifier>,
+    ) -> Result<Message, anyhow::Error> {
+        let bytes = message.to_vec()?;
+        let len = u16::try_from(bytes.len()).context("dns message too large")?;
+
+        let buf = tokio::time::timeout(EXCHANGE_TIMEOUT, async {
+            let mut stream = TcpStream::connect(self.server).await?;
+            stream.write_u16(len).await?;
+            stream.write_all(&bytes).await?;
+
+            let len = stream.read_u16().await?;
+            let mut buf = vec![0; len as usize];
+            stream.read_exact(&mut buf).await?;
+            Ok::<_, std::io::Error>(buf)
+        })
+        .await
+        .with_context(|| format!("no dns response from {} in time", self.server))??;
+
+        let response = Message::from_vec(&buf)?;
+        if response.id() != message.id() || response.message_type() != MessageType::Response {
+            bail!(
+                "dns response from {} does not match the request",
+                self.server
+            );
+        }
+        if let Some(mut verifier) = verifier {
+            verifier(&buf).with_context(|| {
+                format!("dns response from {} fails the tsig check", self.server)
+            })?;
+        }
+
+        Ok(response)
+    }
+}
+
+#[async_trait::async_trait]
+impl DnsProvider for Rfc2136Provider {
+    async fn create_txt_record(&self, fqdn: &str, value: &str) -> Result<(), anyhow::Error> {
+        let name = Name::from_str(fqdn)?;
+        let zone = self.zone_for(&name).await?;
+
+        let message = update_message::append(Self::txt_rrset(&name, value), zone, false, false);
+        self.update(message).await
+    }
+
+    async fn delete_txt_record(&self, fqdn: &str, value: &str) -> Result<(), anyhow::Error> {
+        let name = Name::from_str(fqdn)?;
+        let zone = self.zone_for(&name).await?;
+
+        let message = update_message::delete_by_rdata(Self::txt_rrset(&name, value), zone, false);
+        self.update(message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use hickory_proto::{
+        op::{Message, MessageType, OpCode, ResponseCode},
+        rr::{
+            dnssec::{
+                rdata::{
+                    tsig::{make_tsig_record, message_tbs, TsigAlgorithm, TSIG},
+                    DNSSECRData,
+                },
+                tsig::TSigner,
+            },
+            DNSClass, Name, RData, RecordType,
+        },
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{DnsProvider, Rfc2136Provider};
+
+    const KEY_NAME: &str = "acme-key.";
+    const SECRET: &[u8] = b"0123456789abcdef";
+
+    fn provider(server: std::net::SocketAddr) -> Rfc2136Provider {
+        Rfc2136Provider::new(server, Some("example.com."))
+            .unwrap()
+            .with_tsig(KEY_NAME, "hmac-sha256", SECRET.to_vec())
+            .unwrap()
+    }
+
+    /// Accepts a single request and answers it with NOERROR, signed with `secret` when the
+    /// request is signed
+    async fn serve_once(listener: &TcpListener, secret: &[u8]) -> Result<Message, anyhow::Error> {
+        let (mut stream, _) = listener.accept().await?;
+        let len = stream.read_u16().await?;
+        let mut buf = vec![0; len as usize];
+        stream.read_exact(&mut buf).await?;
+        let request = Message::from_vec(&buf)?;
+
+        let mut response = Message::new();
+        response
+            .set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .set_op_code(request.op_code())
+            .set_response_code(ResponseCode::NoError);
+        if let Some(Some(RData::DNSSEC(DNSSECRData::TSIG(request_tsig)))) =
+            request.signature().first().map(|r| r.data())
+        {
+            let key_name = Name::from_str(KEY_NAME)?;
+            let signer = TSigner::new(
+                secret.to_vec(),
+                TsigAlgorithm::HmacSha256,
+                key_name.clone(),
+                300,
+            )?;
+            let now = chrono::Utc::now().timestamp() as u64;
+            let pre_tsig = TSIG::new(
+                TsigAlgorithm::HmacSha256,
+                now,
+                300,
+                Vec::new(),
+                request.id(),
+                0,
+                Vec::new(),
+            );
+            let tbs = message_tbs(Some(request_tsig.mac()), &response, &pre_tsig, &key_name)?;
+            let mac = signer.sign(&tbs)?;
+            response.add_tsig(make_tsig_record(key_name, pre_tsig.set_mac(mac)));
+        }
+
+        let bytes = response.to_vec()?;
+        stream.write_u16(u16::try_from(bytes.len())?).await?;
+        stream.write_all(&bytes).await?;
+
+        Ok(request)
+    }
+
+    #[tokio::test]
+    async fn test_create_and_delete_txt_record() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let create = serve_once(&listener, SECRET).await.unwrap();
+            let delete = serve_once(&listener, SECRET).await.unwrap();
+            (create, delete)
+        });
+
+        let provider = provider(server);
+        provider
+            .create_txt_record("_acme-challenge.dns.example.com.", "token-value")
+            .await
+            .unwrap();
+        provider
+            .delete_txt_record("_acme-challenge.dns.example.com.", "token-value")
+            .await
+            .unwrap();
+
+        let (create, delete) = handle.await.unwrap();
+        for (message, class) in [(create, DNSClass::IN), (delete, DNSClass::NONE)] {
+            assert_eq!(message.op_code(), OpCode::Update);
+            assert_eq!(message.queries()[0].name().to_string(), "example.com.");
+            assert!(message
+                .signature()
+                .iter()
+                .any(|r| r.record_type() == RecordType::TSIG));
+
+            let update = &message.name_servers()[0];
+            assert_eq!(
+                update.name().to_string(),
+                "_acme-challenge.dns.example.com."
+            );
+            assert_eq!(update.dns_class(), class);
+            match update.data() {
+                Some(RData::TXT(txt)) => assert_eq!(txt.to_string(), "token-value"),
+                data => panic!("unexpected update data {data:?}"),
+            }
+        }
+    }
+    #[tokio::test]
+    async fn test_response_signed_with_another_key() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = listener.local_addr().unwrap();
+        let handle =
+            tokio::spawn(async move { serve_once(&listener, b"fedcba9876543210").await.unwrap() });
+
+        let err = provider(server)
+            .create_txt_record("_acme-challenge.dns.example.com.", "token-value")
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("fails the tsig check"),
+            "{err:#}"
+        );
+        handle.await.unwrap();
+    }
+}
@@ -0,0 +1,28 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Pending HTTP-01 challenges, keyed by token.
+/// The http servers answer `/.well-known/acme-challenge/<token>` from here.
+#[derive(Debug, Clone, Default)]
+pub struct Http01Challenges {
+    key_authorizations: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl Http01Challenges {
+    pub fn insert(&self, token: &str, key_authorization: &str) {
+        self.key_authorizations
+            .lock()
+            .unwrap()
+            .insert(token.to_string(), key_authorization.to_string());
+    }
+
+    pub fn remove(&self, token: &str) {
+        self.key_authorizations.lock().unwrap().remove(token);
+    }
+
+    pub fn get(&self, token: &str) -> Option<String> {
+        self.key_authorizations.lock().unwrap().get(token).cloned()
+    }
+}
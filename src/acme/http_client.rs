@@ -0,0 +1,204 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http::{header, response::Parts, Request};
+use http_body_util::{BodyExt, Full};
+use hyper_rustls::HttpsConnector;
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use instant_acme::{BytesResponse, HttpClient};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a request may take, from connecting to reading the whole response body
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+struct ChainState {
+    alternates: Vec<String>,
+    certificate_override: Option<String>,
+}
+
+impl ChainState {
+    /// Collects the alternate chain urls of a certificate download, and points the certificate
+    /// url of an order at the requested alternate. The order is not signed by the server, so
+    /// instant-acme then downloads the alternate through its usual signed request.
+    fn inspect(&mut self, parts: &mut Parts, body: Bytes) -> Bytes {
+        let content_type = parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+
+        if content_type.starts_with("application/pem-certificate-chain") {
+            self.alternates = parts
+                .headers
+                .get_all(header::LINK)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(parse_alternate_links)
+                .collect();
+            return body;
+        }
+
+        let Some(url) = self.certificate_override.take() else {
+            return body;
+        };
+
+        let mut order = match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(serde_json::Value::Object(order)) if order.contains_key("certificate") => order,
+            _ => {
+                self.certificate_override = Some(url);
+                return body;
+            }
+        };
+        order.insert("certificate".to_string(), url.into());
+
+        parts.headers.remove(header::CONTENT_LENGTH);
+        serde_json::to_vec(&order).map(Bytes::from).unwrap_or(body)
+    }
+}
+
+/// Returns the urls of the `Link: <url>;rel="alternate"` header values
+fn parse_alternate_links(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter_map(|link| {
+            let (url, params) = link.trim().strip_prefix('<')?.split_once('>')?;
+            let alternate = params
+                .split(';')
+                .map(|p| p.trim().replace('"', ""))
+                .any(|p| p == "rel=alternate");
+            alternate.then(|| url.to_string())
+        })
+        .collect()
+}
+
+/// The http client of the ACME account. On top of the plain requests, it gives access to the
+/// alternate certificate chains, which instant-acme does not expose.
+#[derive(Clone)]
+pub struct AcmeHttpClient {
+    client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    state: Arc<Mutex<ChainState>>,
+}
+
+impl AcmeHttpClient {
+    pub fn new() -> Result<Self, anyhow::Error> {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_connect_timeout(Some(CONNECT_TIMEOUT));
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_only()
+            .enable_http1()
+            .enable_http2()
+            .wrap_connector(http);
+        let client = Client::builder(TokioExecutor::new()).build(connector);
+
+        Ok(Self {
+            client,
+            state: Default::default(),
+        })
+    }
+
+    /// Returns the alternate chains linked from the last certificate download
+    pub fn take_alternates(&self) -> Vec<String> {
+        std::mem::take(&mut self.state.lock().unwrap().alternates)
+    }
+
+    /// Makes the next order fetched point at `url` as its certificate
+    pub fn override_certificate_url(&self, url: &str) {
+        self.state.lock().unwrap().certificate_override = Some(url.to_string());
+    }
+}
+
+impl HttpClient for AcmeHttpClient {
+    fn request(
+        &self,
+        req: Request<Full<Bytes>>,
+    ) -> Pin<Box<dyn Future<Output = Result<BytesResponse, instant_acme::Error>> + Send>> {
+        let fut = self.client.request(req);
+        let state = self.state.clone();
+        Box::pin(async move {
+            let response = async {
+                let (parts, body) = fut.await?.into_parts();
+                let body = body.collect().await?.to_bytes();
+                Ok::<_, instant_acme::Error>((parts, body))
+            };
+            let (mut parts, body) = tokio::time::timeout(REQUEST_TIMEOUT, response)
+                .await
+                .map_err(|_| {
+                    let message = format!("acme request did not finish within {REQUEST_TIMEOUT:?}");
+                    instant_acme::Error::Other(message.into())
+                })??;
+            let body = state.lock().unwrap().inspect(&mut parts, body);
+
+            Ok(BytesResponse {
+                parts,
+                body: Box::new(body),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http::{header, Response};
+
+    use super::{parse_alternate_links, ChainState};
+
+    #[test]
+    fn test_parse_alternate_links() {
+        let value =
+            r#"<https://ca.test/dir>;rel="index", <https://ca.test/cert/1/1>;rel="alternate""#;
+        assert_eq!(
+            parse_alternate_links(value),
+            vec!["https://ca.test/cert/1/1".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_chain_state_inspect() {
+        let mut state = ChainState::default();
+
+        let (mut parts, _) = Response::builder()
+            .header(header::CONTENT_TYPE, "application/pem-certificate-chain")
+            .header(header::LINK, "<https://ca.test/cert/1/1>;rel=\"alternate\"")
+            .header(header::LINK, "<https://ca.test/cert/1/2>;rel=\"alternate\"")
+            .body(())
+            .unwrap()
+            .into_parts();
+        state.inspect(
+            &mut parts,
+            Bytes::from_static(b"-----BEGIN CERTIFICATE-----"),
+        );
+        assert_eq!(
+            state.alternates,
+            vec!["https://ca.test/cert/1/1", "https://ca.test/cert/1/2"]
+        );
+
+        state.certificate_override = Some("https://ca.test/cert/1/2".to_string());
+        let (mut parts, _) = Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_LENGTH, "64")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let body =
+            Bytes::from_static(br#"{"status":"valid","certificate":"https://ca.test/cert/1"}"#);
+        let body = state.inspect(&mut parts, body);
+
+        let order: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(order["certificate"], "https://ca.test/cert/1/2");
+        assert_eq!(order["status"], "valid");
+        assert!(parts.headers.get(header::CONTENT_LENGTH).is_none());
+        assert!(state.certificate_override.is_none());
+    }
+}
@@ -0,0 +1,13 @@
+mod account;
+mod challenge;
+mod client;
+mod dns01;
+mod http01;
+mod http_client;
+
+pub use account::*;
+pub use challenge::*;
+pub use client::*;
+pub use dns01::*;
+pub use http01::*;
+pub use http_client::*;
@@ -0,0 +1,467 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Duration, Utc};
+use rcgen::{CertificateParams, DnType, KeyPair, RsaKeySize};
+use tokio::io::AsyncWriteExt;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::FromDer;
+
+pub const CERTS_DIR: &str = "./certs";
+pub const CERT_FILE: &str = "./certs/fullchain.pem";
+pub const KEY_FILE: &str = "./certs/privkey.pem";
+
+/// Set as the organization of fallback certificates, so they are replaced as soon as possible
+const FALLBACK_ORGANIZATION: &str = "dnsdist-acme fallback";
+const FALLBACK_VALIDITY_DAYS: i64 = 30;
+
+/// Writes the file through a temporary file and a rename, so readers never see a partial write
+async fn write_file_atomic(path: &Path, content: &[u8], mode: u32) -> Result<(), anyhow::Error> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    let mut tmp_path = PathBuf::from(path).into_os_string();
+    tmp_path.push(".tmp");
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(&tmp_path)
+        .await?;
+    file.write_all(content).await?;
+    file.sync_all().await?;
+
+    tokio::fs::rename(&tmp_path, path).await?;
+
+    Ok(())
+}
+
+/// Writes a file that holds secrets, readable only by the current user
+pub async fn write_private_file(path: &Path, content: &[u8]) -> Result<(), anyhow::Error> {
+    write_file_atomic(path, content, 0o600).await
+}
+
+/// How a list of domains is split into certificates
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CertMode {
+    /// One certificate with every domain as a subject alternative name
+    San,
+    /// One certificate per domain
+    Separate,
+}
+
+/// The algorithm of the certificate private keys
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    #[value(name = "ecdsa-p256")]
+    EcdsaP256,
+    #[value(name = "ecdsa-p384")]
+    EcdsaP384,
+    Rsa2048,
+    Rsa4096,
+}
+
+impl KeyType {
+    pub fn generate(&self) -> Result<KeyPair, anyhow::Error> {
+        let key_pair = match self {
+            KeyType::EcdsaP256 => KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256)?,
+            KeyType::EcdsaP384 => KeyPair::generate_for(&rcgen::PKCS_ECDSA_P384_SHA384)?,
+            KeyType::Rsa2048 => {
+                KeyPair::generate_rsa_for(&rcgen::PKCS_RSA_SHA256, RsaKeySize::_2048)?
+            }
+            KeyType::Rsa4096 => {
+                KeyPair::generate_rsa_for(&rcgen::PKCS_RSA_SHA256, RsaKeySize::_4096)?
+            }
+        };
+        Ok(key_pair)
+    }
+
+    /// Returns the type of an existing key, if it is one of the supported ones
+    pub fn of(key_pair: &KeyPair) -> Option<Self> {
+        let der = key_pair.public_key_der();
+        let (_, spki) = x509_parser::x509::SubjectPublicKeyInfo::from_der(&der).ok()?;
+        let key_size = spki.parsed().ok()?.key_size();
+
+        let alg = key_pair.algorithm();
+        if alg == &rcgen::PKCS_ECDSA_P256_SHA256 {
+            Some(KeyType::EcdsaP256)
+        } else if alg == &rcgen::PKCS_ECDSA_P384_SHA384 {
+            Some(KeyType::EcdsaP384)
+        } else if alg == &rcgen::PKCS_RSA_SHA256 && key_size == 2048 {
+            Some(KeyType::Rsa2048)
+        } else if alg == &rcgen::PKCS_RSA_SHA256 && key_size == 4096 {
+            Some(KeyType::Rsa4096)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether a renewed certificate keeps the private key of the certificate it replaces
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyPolicy {
+    /// A new key is generated for every certificate
+    Rotate,
+    /// The existing key is kept, as long as it has the configured key type
+    Reuse,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertificatePaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// A certificate to obtain, and where to keep it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertificateSpec {
+    pub domains: Vec<String>,
+    pub paths: CertificatePaths,
+}
+
+/// Lays out the certificates for the domains. The first certificate is always kept at
+/// `CERT_FILE`/`KEY_FILE`, it is the default for clients that do not send an SNI.
+pub fn certificate_specs(domains: &[String], mode: CertMode) -> Vec<CertificateSpec> {
+    let default_paths = CertificatePaths {
+        cert: PathBuf::from(CERT_FILE),
+        key: PathBuf::from(KEY_FILE),
+    };
+
+    match mode {
+        CertMode::San => vec![CertificateSpec {
+            domains: domains.to_vec(),
+            paths: default_paths,
+        }],
+        CertMode::Separate => domains
+            .iter()
+            .enumerate()
+            .map(|(i, domain)| {
+                let paths = if i == 0 {
+                    default_paths.clone()
+                } else {
+                    // a wildcard is kept as `_.example.com`, so the directory name needs no quoting
+                    let dir = PathBuf::from(CERTS_DIR).join(domain.replace('*', "_"));
+                    CertificatePaths {
+                        cert: dir.join("fullchain.pem"),
+                        key: dir.join("privkey.pem"),
+                    }
+                };
+                CertificateSpec {
+                    domains: vec![domain.to_string()],
+                    paths,
+                }
+            })
+            .collect(),
+    }
+}
+
+/// The versions of the certificates written to a directory, the newest ones are kept
+const VERSIONS_DIR: &str = ".versions";
+const KEPT_VERSIONS: usize = 2;
+
+/// The symlink to the current version, the certificate and key paths link through it
+const LIVE_LINK: &str = ".live";
+
+/// Replaces `link` with a symlink to `target` through a rename, so it always points somewhere
+async fn replace_symlink(target: &Path, link: &Path) -> Result<(), anyhow::Error> {
+    let mut tmp_path = PathBuf::from(link).into_os_string();
+    tmp_path.push(".tmp");
+    let _ = tokio::fs::remove_file(&tmp_path).await;
+
+    tokio::fs::symlink(target, &tmp_path).await?;
+    tokio::fs::rename(&tmp_path, link).await?;
+    Ok(())
+}
+
+/// Writes the certificate chain and its private key to the paths that dnsdist and the https
+/// server load. Both are written to a new version directory, and the paths link to it through
+/// a single symlink that is switched at once, so the key and certificate loaded always match.
+pub async fn write_certificate(
+    paths: &CertificatePaths,
+    cert_pem: &str,
+    key_pem: &str,
+) -> Result<(), anyhow::Error> {
+    let dir = paths.cert.parent().unwrap_or(Path::new("."));
+    anyhow::ensure!(
+        paths.key.parent().unwrap_or(Path::new(".")) == dir,
+        "the certificate and its key must be in the same directory"
+    );
+    let cert_name = paths.cert.file_name().context("invalid certificate path")?;
+    let key_name = paths.key.file_name().context("invalid key path")?;
+
+    // named after the time, so the names sort from the oldest to the newest
+    let version = format!("{:020}", Utc::now().timestamp_micros());
+    let version_dir = Path::new(VERSIONS_DIR).join(&version);
+    write_private_file(&dir.join(&version_dir).join(key_name), key_pem.as_bytes()).await?;
+    write_file_atomic(
+        &dir.join(&version_dir).join(cert_name),
+        cert_pem.as_bytes(),
+        0o644,
+    )
+    .await?;
+
+    replace_symlink(&version_dir, &dir.join(LIVE_LINK)).await?;
+    for name in [cert_name, key_name] {
+        let target = Path::new(LIVE_LINK).join(name);
+        let path = dir.join(name);
+        if tokio::fs::read_link(&path).await.ok().as_ref() != Some(&target) {
+            replace_symlink(&target, &path).await?;
+        }
+    }
+
+    // the previous version is kept for readers that resolved the symlink just before the switch
+    let versions = dir.join(VERSIONS_DIR);
+    let mut names = Vec::new();
+    let mut entries = tokio::fs::read_dir(&versions).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        names.push(entry.file_name());
+    }
+    names.sort();
+    for name in names.iter().rev().skip(KEPT_VERSIONS) {
+        tokio::fs::remove_dir_all(versions.join(name)).await?;
+    }
+
+    Ok(())
+}
+
+/// A CA that signs the fallback certificates, so clients that trust it accept them
+pub struct LocalCa {
+    params: CertificateParams,
+    key_pair: KeyPair,
+}
+
+impl LocalCa {
+    pub async fn load(cert_path: &Path, key_path: &Path) -> Result<Self, anyhow::Error> {
+        let cert_pem = tokio::fs::read_to_string(cert_path).await?;
+        let key_pem = tokio::fs::read_to_string(key_path).await?;
+
+        let params = CertificateParams::from_ca_cert_pem(&cert_pem)?;
+        let key_pair = KeyPair::from_pem(&key_pem)?;
+        Ok(Self { params, key_pair })
+    }
+}
+
+/// Writes a short lived certificate for the domains with a `key_type` key, signed by `ca` or
+/// else self-signed. It lets the tls listeners start before a real certificate is obtained.
+pub async fn write_fallback_certificate(
+    spec: &CertificateSpec,
+    key_type: KeyType,
+    ca: Option<&LocalCa>,
+) -> Result<(), anyhow::Error> {
+    let mut params = CertificateParams::new(spec.domains.to_vec())?;
+    let common_name = spec
+        .domains
+        .first()
+        .map(|d| d.as_str())
+        .unwrap_or("localhost");
+    params
+        .distinguished_name
+        .push(DnType::CommonName, common_name);
+    params
+        .distinguished_name
+        .push(DnType::OrganizationName, FALLBACK_ORGANIZATION);
+
+    let not_before = Utc::now() - Duration::days(1);
+    let not_after = Utc::now() + Duration::days(FALLBACK_VALIDITY_DAYS);
+    params.not_before = rcgen::date_time_ymd(
+        not_before.year(),
+        not_before.month() as u8,
+        not_before.day() as u8,
+    );
+    params.not_after = rcgen::date_time_ymd(
+        not_after.year(),
+        not_after.month() as u8,
+        not_after.day() as u8,
+    );
+
+    let key_pair = key_type.generate()?;
+    let cert = match ca {
+        Some(ca) => {
+            let issuer = ca.params.clone().self_signed(&ca.key_pair)?;
+            params.signed_by(&key_pair, &issuer, &ca.key_pair)?
+        }
+        None => params.self_signed(&key_pair)?,
+    };
+
+    write_certificate(&spec.paths, &cert.pem(), &key_pair.serialize_pem()).await
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub names: Vec<String>,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+}
+
+impl CertificateInfo {
+    /// Reads the leaf certificate from a pem file
+    pub async fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let content = tokio::fs::read(path).await?;
+        Self::from_pem(&content)
+    }
+
+    pub fn from_pem(content: &[u8]) -> Result<Self, anyhow::Error> {
+        let (_, pem) = x509_parser::pem::parse_x509_pem(content)?;
+        let cert = pem.parse_x509()?;
+
+        let names = match cert.subject_alternative_name()? {
+            Some(san) => san
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) => Some(name.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let not_before = cert.validity().not_before.timestamp();
+        let not_before = DateTime::from_timestamp(not_before, 0).context("invalid notBefore")?;
+        let not_after = cert.validity().not_after.timestamp();
+        let not_after = DateTime::from_timestamp(not_after, 0).context("invalid notAfter")?;
+
+        Ok(Self {
+            subject: cert.subject().to_string(),
+            issuer: cert.issuer().to_string(),
+            names,
+            not_before,
+            not_after,
+        })
+    }
+
+    /// Returns true for the certificates written by `write_fallback_certificate`
+    pub fn is_fallback(&self) -> bool {
+        self.subject
+            .split(", ")
+            .any(|rdn| rdn == format!("O={FALLBACK_ORGANIZATION}"))
+    }
+
+    /// Returns the whole days left until the certificate expires, negative once expired
+    pub fn days_remaining(&self) -> i64 {
+        (self.not_after - Utc::now()).num_days()
+    }
+
+    /// Returns true if the certificate covers all the domains and is valid for longer than `min_validity`
+    pub fn is_usable_for(&self, domains: &[String], min_validity: Duration) -> bool {
+        let covers_domains = domains.iter().all(|d| self.names.contains(d));
+        covers_domains && self.not_after - Utc::now() > min_validity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{
+        certificate_specs, write_certificate, write_fallback_certificate, CertMode,
+        CertificateInfo, CertificatePaths, CertificateSpec, KeyType, LocalCa, CERT_FILE,
+        VERSIONS_DIR,
+    };
+
+    #[test]
+    fn test_certificate_specs() {
+        let domains = vec!["dns.example.com".to_string(), "*.example.net".to_string()];
+
+        let specs = certificate_specs(&domains, CertMode::San);
+        assert_eq!(specs.len(), 1);
+        assert_eq!(specs[0].domains, domains);
+        assert_eq!(specs[0].paths.cert, PathBuf::from(CERT_FILE));
+
+        let specs = certificate_specs(&domains, CertMode::Separate);
+        assert_eq!(specs.len(), 2);
+        assert_eq!(specs[0].domains, vec!["dns.example.com"]);
+        assert_eq!(specs[0].paths.cert, PathBuf::from(CERT_FILE));
+        assert_eq!(specs[1].domains, vec!["*.example.net"]);
+        assert_eq!(
+            specs[1].paths.cert,
+            PathBuf::from("./certs/_.example.net/fullchain.pem")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_fallback_certificate() {
+        let dir = std::env::temp_dir().join(format!("fallback-test-{}", rand::random::<u32>()));
+        let spec = CertificateSpec {
+            domains: vec!["dns.example.com".to_string()],
+            paths: CertificatePaths {
+                cert: dir.join("fullchain.pem"),
+                key: dir.join("privkey.pem"),
+            },
+        };
+
+        write_fallback_certificate(&spec, KeyType::Rsa2048, None)
+            .await
+            .unwrap();
+        let info = CertificateInfo::load(&spec.paths.cert).await.unwrap();
+        assert!(info.is_fallback());
+        assert_eq!(info.issuer, info.subject);
+        assert_eq!(info.names, spec.domains);
+        let key_pem = tokio::fs::read_to_string(&spec.paths.key).await.unwrap();
+        let key_pair = rcgen::KeyPair::from_pem(&key_pem).unwrap();
+        assert_eq!(KeyType::of(&key_pair), Some(KeyType::Rsa2048));
+
+        let mut ca_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Local Test CA");
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+        let ca_cert_path = dir.join("ca.pem");
+        let ca_key_path = dir.join("ca-key.pem");
+        tokio::fs::write(&ca_cert_path, ca_cert.pem())
+            .await
+            .unwrap();
+        tokio::fs::write(&ca_key_path, ca_key.serialize_pem())
+            .await
+            .unwrap();
+
+        let ca = LocalCa::load(&ca_cert_path, &ca_key_path).await.unwrap();
+        write_fallback_certificate(&spec, KeyType::EcdsaP256, Some(&ca))
+            .await
+            .unwrap();
+        let info = CertificateInfo::load(&spec.paths.cert).await.unwrap();
+        assert!(info.is_fallback());
+        assert_eq!(info.issuer, "CN=Local Test CA");
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_certificate() {
+        let dir = std::env::temp_dir().join(format!("write-cert-test-{}", rand::random::<u32>()));
+        let paths = CertificatePaths {
+            cert: dir.join("fullchain.pem"),
+            key: dir.join("privkey.pem"),
+        };
+
+        for i in 0..3 {
+            let cert = format!("cert {i}");
+            let key = format!("key {i}");
+            write_certificate(&paths, &cert, &key).await.unwrap();
+            assert_eq!(tokio::fs::read_to_string(&paths.cert).await.unwrap(), cert);
+            assert_eq!(tokio::fs::read_to_string(&paths.key).await.unwrap(), key);
+        }
+        let versions = std::fs::read_dir(dir.join(VERSIONS_DIR)).unwrap().count();
+        assert_eq!(versions, 2, "older versions are removed");
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn test_key_type() {
+        for key_type in [KeyType::EcdsaP256, KeyType::EcdsaP384, KeyType::Rsa2048] {
+            let key_pair = key_type.generate().unwrap();
+            let pem = key_pair.serialize_pem();
+            let key_pair = rcgen::KeyPair::from_pem(&pem).unwrap();
+            assert_eq!(KeyType::of(&key_pair), Some(key_type));
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+
+/// A client network in CIDR notation, a plain address matches only itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Netmask(String);
+
+impl FromStr for Netmask {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .with_context(|| format!("invalid netmask {s:?}"))?;
+        if let Some(prefix) = prefix {
+            let max = if addr.is_ipv4() { 32 } else { 128 };
+            match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= max => {}
+                _ => bail!("invalid netmask {s:?}, the prefix length must be 0 to {max}"),
+            }
+        }
+
+        Ok(Netmask(s.to_string()))
+    }
+}
+
+impl fmt::Display for Netmask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for Netmask {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The listeners that have their own acl
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Dns,
+    Doh,
+    Dot,
+}
+
+impl Protocol {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Dns => "dns",
+            Protocol::Doh => "doh",
+            Protocol::Dot => "dot",
+        }
+    }
+}
+
+/// Denied networks win over allowed ones. An empty allow list allows every client.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Acl {
+    pub allow: Vec<Netmask>,
+    pub deny: Vec<Netmask>,
+}
+
+impl Acl {
+    pub fn is_open(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn extend(&mut self, other: &Acl) {
+        self.allow.extend(other.allow.iter().cloned());
+        self.deny.extend(other.deny.iter().cloned());
+    }
+}
+
+/// The networks that may query some listener, for the global acl of dnsdist. The listener acls
+/// narrow it down further. It allows every network when one of the acls has no allow list.
+pub fn allowed_networks<'a>(acls: impl IntoIterator<Item = &'a Acl>) -> Vec<String> {
+    let mut networks: Vec<String> = Vec::new();
+    for acl in acls {
+        if acl.allow.is_empty() {
+            networks.clear();
+            break;
+        }
+        for netmask in acl.allow.iter() {
+            if !networks.iter().any(|n| n == netmask.as_ref()) {
+                networks.push(netmask.to_string());
+            }
+        }
+    }
+
+    match networks.is_empty() {
+        true => vec!["0.0.0.0/0".to_string(), "::/0".to_string()],
+        false => networks,
+    }
+}
+
+/// The acls of every listener
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ListenerAcls {
+    pub dns: Acl,
+    pub doh: Acl,
+    pub dot: Acl,
+}
+
+impl ListenerAcls {
+    pub fn get(&self, protocol: Protocol) -> &Acl {
+        match protocol {
+            Protocol::Dns => &self.dns,
+            Protocol::Doh => &self.doh,
+            Protocol::Dot => &self.dot,
+        }
+    }
+
+    fn get_mut(&mut self, protocol: Protocol) -> &mut Acl {
+        match protocol {
+            Protocol::Dns => &mut self.dns,
+            Protocol::Doh => &mut self.doh,
+            Protocol::Dot => &mut self.dot,
+        }
+    }
+
+    /// Parses an acl file, one `<dns|doh|dot> <allow|deny> <netmask>` entry per line. Empty
+    /// lines and lines starting with `#` are skipped.
+    pub fn parse(content: &str) -> Result<Self, anyhow::Error> {
+        let mut acls = ListenerAcls::default();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parse_line = || -> Result<(), anyhow::Error> {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let [protocol, kind, netmask] = fields[..] else {
+                    bail!("expected `<dns|doh|dot> <allow|deny> <netmask>`");
+                };
+                let protocol = Protocol::from_str(protocol, true).map_err(anyhow::Error::msg)?;
+                let netmask = netmask.parse()?;
+                let acl = acls.get_mut(protocol);
+                match kind {
+                    "allow" => acl.allow.push(netmask),
+                    "deny" => acl.deny.push(netmask),
+                    _ => bail!("unknown acl entry {kind:?}, expected allow or deny"),
+                }
+                Ok(())
+            };
+            parse_line().with_context(|| format!("acl line {}", index + 1))?;
+        }
+
+        Ok(acls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{allowed_networks, ListenerAcls, Netmask};
+
+    #[test]
+    fn test_parse_acl_file() {
+        let acls = ListenerAcls::parse(
+            "# home network\ndns allow 192.168.0.0/16\n\ndns deny 192.168.1.10\ndoh deny 2001:db8::/32\n",
+        )
+        .unwrap();
+        assert_eq!(acls.dns.allow, vec!["192.168.0.0/16".parse().unwrap()]);
+        assert_eq!(acls.dns.deny, vec!["192.168.1.10".parse().unwrap()]);
+        assert_eq!(acls.doh.deny, vec!["2001:db8::/32".parse().unwrap()]);
+        assert!(acls.dot.is_open());
+
+        assert!(ListenerAcls::parse("dns allow").is_err());
+        assert!(ListenerAcls::parse("dns permit 10.0.0.0/8").is_err());
+        assert!(ListenerAcls::parse("smtp allow 10.0.0.0/8").is_err());
+        assert!("10.0.0.0/33".parse::<Netmask>().is_err());
+        assert!("example.com".parse::<Netmask>().is_err());
+    }
+
+    #[test]
+    fn test_allowed_networks() {
+        let mut acls = ListenerAcls::parse(
+            "dns allow 10.0.0.0/8
+doh allow 10.0.0.0/8
+doh allow ::1
+",
+        )
+        .unwrap();
+        assert_eq!(
+            allowed_networks([&acls.dns, &acls.doh]),
+            ["10.0.0.0/8", "::1"]
+        );
+        acls.doh.allow.clear();
+        assert_eq!(
+            allowed_networks([&acls.dns, &acls.doh]),
+            ["0.0.0.0/0", "::/0"]
+        );
+    }
+}
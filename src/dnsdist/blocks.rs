@@ -0,0 +1,85 @@
+use chrono::{DateTime, Duration, Utc};
+
+/// A network or domain blocked by a dynamic block rule
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct BlockedClient {
+    pub what: String,
+    pub seconds_remaining: u64,
+    pub expires_at: DateTime<Utc>,
+    pub blocks: u64,
+    pub warning: bool,
+    pub action: String,
+    pub reason: String,
+}
+
+/// Lua for the console that prints a tab separated `block` record for each dynamic block, with
+/// what is blocked, the seconds remaining, the blocked queries, the warning flag, the action and
+/// the reason. The network blocks come first, then the domain blocks.
+pub const SHOW_DYN_BLOCKS: &str = r#"
+local now = getCurrentTime()
+local actions = {}
+for name, value in pairs(DNSAction) do actions[value] = name end
+local function show(what, block)
+  local reason = string.gsub(block.reason, '[\t\n]', ' ')
+  print(table.concat({ 'block', what, tostring(block['until'].tv_sec - now.tv_sec), tostring(block.blocks), tostring(block.warning), actions[block.action] or tostring(block.action), reason }, '\t'))
+end
+for what, block in pairs(getDynamicBlocks()) do show(what, block) end
+for what, block in pairs(getDynamicBlocksSMT()) do show(what, block) end
+"#;
+
+fn parse_record(line: &str, now: DateTime<Utc>) -> Option<BlockedClient> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let ["block", what, seconds, blocks, warning, action, reason] = fields[..] else {
+        return None;
+    };
+
+    // a block that just expired is listed until dnsdist purges it
+    let seconds_remaining = seconds.parse::<i64>().ok()?.max(0) as u64;
+    Some(BlockedClient {
+        what: what.to_string(),
+        seconds_remaining,
+        expires_at: now + Duration::seconds(seconds_remaining as i64),
+        blocks: blocks.parse().ok()?,
+        warning: warning == "true",
+        action: action.to_string(),
+        reason: reason.to_string(),
+    })
+}
+
+/// Parses the records printed by `SHOW_DYN_BLOCKS`, other lines are skipped
+pub fn parse_dyn_blocks(output: &str, now: DateTime<Utc>) -> Vec<BlockedClient> {
+    output
+        .lines()
+        .filter_map(|line| parse_record(line, now))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::parse_dyn_blocks;
+
+    #[test]
+    fn test_parse_dyn_blocks() {
+        let output = "block\t192.0.2.1/32\t58\t12\tfalse\tDrop\tExceeded query rate\n\
+                      block\t2001:db8:1234::/48\t-1\t0\ttrue\tRefused\tExceeded NXDOMAIN rate\n\
+                      some warning\n\
+                      block\texample.com.\t5\tmany\tfalse\tDrop\tdomain\n";
+
+        let now = Utc::now();
+        let blocked = parse_dyn_blocks(output, now);
+        assert_eq!(blocked.len(), 2);
+        assert_eq!(blocked[0].what, "192.0.2.1/32");
+        assert_eq!(blocked[0].seconds_remaining, 58);
+        assert_eq!(blocked[0].expires_at, now + Duration::seconds(58));
+        assert_eq!(blocked[0].blocks, 12);
+        assert!(!blocked[0].warning);
+        assert_eq!(blocked[0].action, "Drop");
+        assert_eq!(blocked[0].reason, "Exceeded query rate");
+        assert_eq!(blocked[1].what, "2001:db8:1234::/48");
+        assert_eq!(blocked[1].seconds_remaining, 0);
+        assert!(blocked[1].warning);
+        assert_eq!(blocked[1].action, "Refused");
+    }
+}
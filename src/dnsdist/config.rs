@@ -0,0 +1,901 @@
+use std::{fmt::Write, net::SocketAddr, path::PathBuf, str::FromStr};
+
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+
+use super::{lua, Acl, DynBlocks, Netmask, Protocol};
+use crate::certs::CertificatePaths;
+
+/// Everything that goes into the dnsdist lua config. The config is rendered at startup and
+/// compared on reloads, so adding a dnsdist feature means adding a field here.
+#[derive(Debug, Clone)]
+pub struct DnsdistConfig {
+    pub listeners: Vec<Listener>,
+    pub backends: Vec<Backend>,
+    pub server_policy: ServerPolicy,
+    /// Networks that are allowed to query any listener, in CIDR notation
+    pub acl: Vec<String>,
+    /// Applied before the other rules, so denied clients are dropped first
+    pub acls: Vec<ListenerAcl>,
+    pub rules: Vec<Rule>,
+    /// Applied after the rules, so routed queries are rate limited too
+    pub routes: Vec<Route>,
+    pub dyn_blocks: Option<DynBlocks>,
+    pub dnstap: Option<Dnstap>,
+    pub console: Option<Console>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Listener {
+    pub address: SocketAddr,
+    pub kind: ListenerKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum ListenerKind {
+    /// Plain DNS over UDP and TCP
+    Dns,
+    /// DNS over HTTPS, served on the given url paths
+    Doh {
+        certificates: Vec<CertificatePaths>,
+        paths: Vec<String>,
+    },
+    /// DNS over TLS
+    Dot { certificates: Vec<CertificatePaths> },
+}
+
+/// An upstream resolver. Parsed from `ADDRESS[;key=value...]`, e.g.
+/// `9.9.9.9:53;weight=2;pool=filtered;health-check=lazy;check-interval=5;check-name=example.com`
+/// or `9.9.9.9:853;transport=dot;subject-name=dns.quad9.net`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backend {
+    pub address: SocketAddr,
+    /// Defaults to `resolver<n>`, by position
+    pub name: Option<String>,
+    /// Used by the wrandom and chashed policies
+    pub weight: Option<u32>,
+    /// Used by the firstAvailable policy, lower first
+    pub order: Option<u32>,
+    /// Backends in a named pool only get the queries that are routed to the pool
+    pub pool: Option<String>,
+    pub health_check: HealthCheckMode,
+    /// Seconds between health checks
+    pub check_interval: Option<u32>,
+    /// The name queried by the health checks
+    pub check_name: Option<String>,
+    pub transport: Transport,
+    /// The url path of a DoH backend, defaults to `/dns-query`
+    pub doh_path: Option<String>,
+    /// The name sent as SNI and expected in the certificate of a DoT or DoH backend
+    pub subject_name: Option<String>,
+    pub validate_certificates: bool,
+    /// A CA bundle to validate the backend certificate with, instead of the system store
+    pub ca_store: Option<PathBuf>,
+}
+
+/// The protocol used to forward queries to a backend
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    /// Plain DNS over UDP and TCP
+    Dns,
+    /// DNS over TLS
+    Dot,
+    /// DNS over HTTPS
+    Doh,
+}
+
+/// How dnsdist decides whether a backend is up
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HealthCheckMode {
+    /// Sends a health check query every interval
+    Auto,
+    /// Only sends health checks after queries to the backend failed
+    Lazy,
+    /// Always considered up
+    Up,
+    /// Always considered down
+    Down,
+}
+
+/// How dnsdist picks a backend for a query
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServerPolicy {
+    /// The first backend that is up, by order
+    FirstAvailable,
+    /// The backend with the fewest outstanding queries
+    LeastOutstanding,
+    /// A random backend, by weight
+    Wrandom,
+    /// A backend chosen by a hash of the query name, by weight
+    Chashed,
+}
+
+/// A query rule, the action is applied to every query that matches the selector
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// Shown in the rule listings of dnsdist
+    pub name: Option<String>,
+    pub selector: Selector,
+    pub action: Action,
+}
+
+/// The acl of the listeners of a protocol, told apart by their port
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenerAcl {
+    pub protocol: Protocol,
+    pub port: u16,
+    pub acl: Acl,
+}
+
+#[derive(Debug, Clone)]
+pub enum Selector {
+    /// Matches queries received on a local port
+    DestinationPort(u16),
+    /// Matches queries from the networks
+    Netmasks(Vec<Netmask>),
+    Not(Box<Selector>),
+    Or(Vec<Selector>),
+    And(Vec<Selector>),
+    /// Matches queries from a network that sends more than `qps` queries per second. Clients
+    /// are grouped into networks by the prefix lengths.
+    MaxQpsIp {
+        qps: u32,
+        v4_prefix: u8,
+        v6_prefix: u8,
+    },
+    /// Matches the names under the suffixes of a `newSuffixMatchNode()` lua variable
+    SuffixMatch(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    Drop,
+    /// Responds with the TC bit set
+    Truncate,
+    Refused,
+    /// Sends the query to the backends of a pool
+    Pool(String),
+}
+
+/// Sends the queries for names under the suffixes to the backends of a pool. Parsed from
+/// `SUFFIX[;SUFFIX...]=POOL`, e.g. `corp.example;lan=internal`
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Route {
+    /// Fully qualified and lowercase, e.g. `corp.example.`
+    pub suffixes: Vec<String>,
+    pub pool: String,
+}
+
+/// A route and the backends it sends queries to, for the routes api
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RouteStatus {
+    #[serde(flatten)]
+    pub route: Route,
+    pub backends: Vec<String>,
+}
+
+#[derive(serde::Serialize, Debug, Clone, Default)]
+pub struct RoutesOutput {
+    pub routes: Vec<RouteStatus>,
+    /// The backends outside of a pool, that answer the queries no route matches
+    pub default_backends: Vec<String>,
+}
+
+/// Where the responses are logged as dnstap messages
+#[derive(Debug, Clone)]
+pub struct Dnstap {
+    pub identity: String,
+    pub socket: PathBuf,
+}
+
+/// The console that the running server is controlled through
+#[derive(Debug, Clone)]
+pub struct Console {
+    pub address: SocketAddr,
+    pub key: String,
+}
+
+impl Listener {
+    pub fn new(address: SocketAddr, kind: ListenerKind) -> Self {
+        Self { address, kind }
+    }
+}
+
+impl Backend {
+    pub fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            name: None,
+            weight: None,
+            order: None,
+            pool: None,
+            health_check: HealthCheckMode::Auto,
+            check_interval: None,
+            check_name: None,
+            transport: Transport::Dns,
+            doh_path: None,
+            subject_name: None,
+            validate_certificates: true,
+            ca_store: None,
+        }
+    }
+
+    fn name(&self, index: usize) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("resolver{}", index + 1),
+        }
+    }
+
+    fn render(&self, index: usize) -> String {
+        let name = self.name(index);
+        let mut fields = vec![
+            ("address", lua::string(&self.address.to_string())),
+            ("name", lua::string(&name)),
+            ("healthCheckMode", lua::string(self.health_check.name())),
+        ];
+        if let Some(weight) = self.weight {
+            fields.push(("weight", weight.to_string()));
+        }
+        if let Some(order) = self.order {
+            fields.push(("order", order.to_string()));
+        }
+        if let Some(pool) = &self.pool {
+            fields.push(("pool", lua::string(pool)));
+        }
+        if let Some(interval) = self.check_interval {
+            fields.push(("checkInterval", interval.to_string()));
+        }
+        if let Some(check_name) = &self.check_name {
+            fields.push(("checkName", lua::string(check_name)));
+        }
+        if self.transport != Transport::Dns {
+            fields.push(("tls", lua::string("openssl")));
+            if let Some(subject_name) = &self.subject_name {
+                fields.push(("subjectName", lua::string(subject_name)));
+            }
+            let validate = self.validate_certificates.to_string();
+            fields.push(("validateCertificates", validate));
+            if let Some(ca_store) = &self.ca_store {
+                fields.push(("caStore", lua::string(&ca_store.display().to_string())));
+            }
+        }
+        if self.transport == Transport::Doh {
+            let path = self.doh_path.as_deref().unwrap_or("/dns-query");
+            fields.push(("dohPath", lua::string(path)));
+        }
+        format!("newServer({})", lua::table(&fields))
+    }
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(';').map(str::trim);
+        let address = parts.next().unwrap_or_default();
+        let address = address
+            .parse()
+            .with_context(|| format!("invalid backend address {address:?}"))?;
+
+        let mut backend = Backend::new(address);
+        for part in parts.filter(|p| !p.is_empty()) {
+            let Some((key, value)) = part.split_once('=') else {
+                bail!("invalid backend option {part:?}, expected key=value");
+            };
+            let invalid = || format!("invalid backend option {part:?}");
+            match key.trim() {
+                "name" => backend.name = Some(value.to_string()),
+                "weight" => backend.weight = Some(value.parse().with_context(invalid)?),
+                "order" => backend.order = Some(value.parse().with_context(invalid)?),
+                "pool" => backend.pool = Some(value.to_string()),
+                "health-check" => {
+                    backend.health_check =
+                        HealthCheckMode::from_str(value, true).map_err(anyhow::Error::msg)?
+                }
+                "check-interval" => {
+                    backend.check_interval = Some(value.parse().with_context(invalid)?)
+                }
+                "check-name" => backend.check_name = Some(value.to_string()),
+                "transport" => {
+                    backend.transport =
+                        Transport::from_str(value, true).map_err(anyhow::Error::msg)?
+                }
+                "doh-path" => backend.doh_path = Some(value.to_string()),
+                "subject-name" => backend.subject_name = Some(value.to_string()),
+                "validate-certificates" => {
+                    backend.validate_certificates = value.parse().with_context(invalid)?
+                }
+                "ca-store" => backend.ca_store = Some(PathBuf::from(value)),
+                _ => bail!("unknown backend option {key:?}"),
+            }
+        }
+
+        let encrypted = backend.transport != Transport::Dns;
+        if !encrypted
+            && (backend.subject_name.is_some()
+                || backend.ca_store.is_some()
+                || !backend.validate_certificates)
+        {
+            bail!("backend {address} sets tls options, but its transport is not dot or doh");
+        }
+        if backend.doh_path.is_some() && backend.transport != Transport::Doh {
+            bail!("backend {address} sets doh-path, but its transport is not doh");
+        }
+        if encrypted && backend.validate_certificates && backend.subject_name.is_none() {
+            bail!("backend {address} needs a subject-name to validate its certificate against");
+        }
+
+        Ok(backend)
+    }
+}
+
+impl FromStr for Route {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((suffixes, pool)) = s.rsplit_once('=') else {
+            bail!("invalid route {s:?}, expected SUFFIX=POOL");
+        };
+        let pool = pool.trim();
+        if pool.is_empty() {
+            bail!("route {s:?} has no pool");
+        }
+
+        let mut normalized = Vec::new();
+        for suffix in suffixes.split(';').map(str::trim) {
+            let name = suffix.trim_end_matches('.').to_lowercase();
+            let valid = !name.is_empty()
+                && name
+                    .split('.')
+                    .all(|label| !label.is_empty() && !label.contains(char::is_whitespace));
+            if !valid {
+                bail!("route {s:?} has an invalid suffix {suffix:?}");
+            }
+            normalized.push(format!("{name}."));
+        }
+
+        Ok(Route {
+            suffixes: normalized,
+            pool: pool.to_string(),
+        })
+    }
+}
+
+impl HealthCheckMode {
+    fn name(&self) -> &'static str {
+        match self {
+            HealthCheckMode::Auto => "auto",
+            HealthCheckMode::Lazy => "lazy",
+            HealthCheckMode::Up => "up",
+            HealthCheckMode::Down => "down",
+        }
+    }
+}
+
+impl ServerPolicy {
+    fn name(&self) -> &'static str {
+        match self {
+            ServerPolicy::FirstAvailable => "firstAvailable",
+            ServerPolicy::LeastOutstanding => "leastOutstanding",
+            ServerPolicy::Wrandom => "wrandom",
+            ServerPolicy::Chashed => "chashed",
+        }
+    }
+}
+
+impl Selector {
+    fn render(&self) -> String {
+        let render_all = |selectors: &[Selector]| -> String {
+            let selectors: Vec<String> = selectors.iter().map(|s| s.render()).collect();
+            format!("{{ {} }}", selectors.join(", "))
+        };
+        match self {
+            Selector::DestinationPort(port) => format!("DSTPortRule({port})"),
+            Selector::Netmasks(netmasks) => {
+                format!(
+                    "NetmaskGroupRule(netmaskGroup({}))",
+                    lua::string_array(netmasks)
+                )
+            }
+            Selector::Not(selector) => format!("NotRule({})", selector.render()),
+            Selector::Or(selectors) => format!("OrRule({})", render_all(selectors)),
+            Selector::And(selectors) => format!("AndRule({})", render_all(selectors)),
+            Selector::MaxQpsIp {
+                qps,
+                v4_prefix,
+                v6_prefix,
+            } => format!("MaxQPSIPRule({qps}, {v4_prefix}, {v6_prefix})"),
+            Selector::SuffixMatch(name) => format!("SuffixMatchNodeRule({name})"),
+        }
+    }
+}
+
+impl Rule {
+    fn render(&self) -> String {
+        let selector = self.selector.render();
+        let action = self.action.render();
+        match &self.name {
+            Some(name) => {
+                let options = lua::table(&[("name", lua::string(name))]);
+                format!("newRuleAction({selector}, {action}, {options})")
+            }
+            None => format!("newRuleAction({selector}, {action})"),
+        }
+    }
+}
+
+impl ListenerAcl {
+    fn rule_name(&self) -> String {
+        format!("acl-{}", self.protocol.name())
+    }
+
+    /// Returns the rule that drops the queries the acl does not allow, none if it allows all
+    fn rule(&self) -> Option<Rule> {
+        let mut rejected = Vec::new();
+        if !self.acl.allow.is_empty() {
+            let allowed = Selector::Netmasks(self.acl.allow.clone());
+            rejected.push(Selector::Not(Box::new(allowed)));
+        }
+        if !self.acl.deny.is_empty() {
+            rejected.push(Selector::Netmasks(self.acl.deny.clone()));
+        }
+        if rejected.is_empty() {
+            return None;
+        }
+
+        Some(Rule {
+            name: Some(self.rule_name()),
+            selector: Selector::And(vec![
+                Selector::DestinationPort(self.port),
+                Selector::Or(rejected),
+            ]),
+            action: Action::Drop,
+        })
+    }
+}
+
+impl Action {
+    fn render(&self) -> String {
+        match self {
+            Action::Drop => "DropAction()".to_string(),
+            Action::Truncate => "TCAction()".to_string(),
+            Action::Refused => "RCodeAction(DNSRCode.REFUSED)".to_string(),
+            Action::Pool(pool) => format!("PoolAction({})", lua::string(pool)),
+        }
+    }
+}
+
+/// Renders the cert and key tables, dnsdist picks between the certificates by SNI
+fn certificate_tables(certificates: &[CertificatePaths]) -> (String, String) {
+    let certs: Vec<String> = certificates
+        .iter()
+        .map(|c| c.cert.display().to_string())
+        .collect();
+    let keys: Vec<String> = certificates
+        .iter()
+        .map(|c| c.key.display().to_string())
+        .collect();
+    (lua::string_array(&certs), lua::string_array(&keys))
+}
+
+impl DnsdistConfig {
+    /// Lists the routes with the backends of their pools
+    pub fn routes_output(&self) -> RoutesOutput {
+        let backends_of = |pool: Option<&str>| -> Vec<String> {
+            self.backends
+                .iter()
+                .filter(|b| b.pool.as_deref() == pool)
+                .map(|b| b.address.to_string())
+                .collect()
+        };
+
+        RoutesOutput {
+            routes: self
+                .routes
+                .iter()
+                .map(|route| RouteStatus {
+                    route: route.clone(),
+                    backends: backends_of(Some(&route.pool)),
+                })
+                .collect(),
+            default_backends: backends_of(None),
+        }
+    }
+
+    fn render_backends(&self) -> String {
+        let mut out = String::new();
+        for (index, backend) in self.backends.iter().enumerate() {
+            let _ = writeln!(out, "{}", backend.render(index));
+        }
+        out
+    }
+
+    fn render_listeners(&self) -> String {
+        let mut out = String::new();
+        let mut first_dns = true;
+        for listener in self.listeners.iter() {
+            let address = lua::string(&listener.address.to_string());
+            // reusePort lets a restarted dnsdist bind the ports before the previous one stops
+            let reuse_port = ("reusePort", "true".to_string());
+            match &listener.kind {
+                ListenerKind::Dns => {
+                    // setLocal replaces the default 127.0.0.1:53 listener
+                    let function = if first_dns { "setLocal" } else { "addLocal" };
+                    first_dns = false;
+                    let options = lua::table(&[reuse_port]);
+                    let _ = writeln!(out, "{function}({address}, {options})");
+                }
+                ListenerKind::Doh {
+                    certificates,
+                    paths,
+                } => {
+                    let (certs, keys) = certificate_tables(certificates);
+                    let options = lua::table(&[
+                        ("doTCP", "true".to_string()),
+                        reuse_port,
+                        ("tcpFastOpenSize", "0".to_string()),
+                    ]);
+                    let paths = lua::string_array(paths);
+                    let _ = writeln!(
+                        out,
+                        "addDOHLocal({address}, {certs}, {keys}, {paths}, {options})"
+                    );
+                }
+                ListenerKind::Dot { certificates } => {
+                    let (certs, keys) = certificate_tables(certificates);
+                    let options = lua::table(&[reuse_port]);
+                    let _ = writeln!(out, "addTLSLocal({address}, {certs}, {keys}, {options})");
+                }
+            }
+        }
+        out
+    }
+
+    fn render_console(&self) -> String {
+        let mut out = String::new();
+        if let Some(console) = &self.console {
+            let _ = writeln!(
+                out,
+                "controlSocket({})",
+                lua::string(&console.address.to_string())
+            );
+            let _ = writeln!(out, "setKey({})", lua::string(&console.key));
+        }
+        out
+    }
+
+    fn render_dyn_blocks(&self) -> String {
+        let mut out = String::new();
+        if let Some(dyn_blocks) = &self.dyn_blocks {
+            dyn_blocks.render(&mut out);
+        }
+        out
+    }
+
+    fn render_dnstap(&self) -> String {
+        let mut out = String::new();
+        if let Some(dnstap) = &self.dnstap {
+            let socket = dnstap.socket.display().to_string();
+            let _ = writeln!(
+                out,
+                "dnstap=newFrameStreamUnixLogger({})",
+                lua::string(&socket)
+            );
+            let _ = writeln!(
+                out,
+                "addResponseAction(AllRule(), DnstapLogResponseAction({}, dnstap))",
+                lua::string(&dnstap.identity)
+            );
+        }
+        out
+    }
+
+    /// Renders the acl rules, then the other rules, then the routes. They are set at once, so
+    /// a reload never leaves dnsdist without its acls.
+    fn render_rules(&self) -> String {
+        let mut out = String::new();
+        let mut rules: Vec<Rule> = self
+            .acls
+            .iter()
+            .filter_map(|acl| acl.rule())
+            .chain(self.rules.iter().cloned())
+            .collect();
+        for (index, route) in self.routes.iter().enumerate() {
+            let name = format!("route{}", index + 1);
+            let _ = writeln!(out, "{name}=newSuffixMatchNode()");
+            let _ = writeln!(out, "{name}:add({})", lua::string_array(&route.suffixes));
+            rules.push(Rule {
+                name: None,
+                selector: Selector::SuffixMatch(name),
+                action: Action::Pool(route.pool.clone()),
+            });
+        }
+
+        let _ = writeln!(out, "setRules({{");
+        for rule in rules.iter() {
+            let _ = writeln!(out, "    {},", rule.render());
+        }
+        let _ = writeln!(out, "}})");
+        out
+    }
+
+    /// Returns the console commands that change a running dnsdist from this config to the new
+    /// one, empty when nothing changed. Returns none when dnsdist has to be restarted instead,
+    /// because its listeners, console or dnstap logging changed.
+    pub fn reload_commands(&self, new: &DnsdistConfig) -> Option<String> {
+        if self.render_listeners() != new.render_listeners()
+            || self.render_console() != new.render_console()
+            || self.render_dnstap() != new.render_dnstap()
+        {
+            return None;
+        }
+
+        let mut out = String::new();
+        if self.render_backends() != new.render_backends() {
+            let old: Vec<(String, String)> = self
+                .backends
+                .iter()
+                .enumerate()
+                .map(|(index, b)| (b.name(index), b.render(index)))
+                .collect();
+            let new: Vec<(String, String)> = new
+                .backends
+                .iter()
+                .enumerate()
+                .map(|(index, b)| (b.name(index), b.render(index)))
+                .collect();
+            for (name, _) in old.iter().filter(|b| !new.contains(b)) {
+                let _ = writeln!(out, "rmServerByName({})", lua::string(name));
+            }
+            for (_, line) in new.iter().filter(|b| !old.contains(b)) {
+                let _ = writeln!(out, "{line}");
+            }
+        }
+        if self.server_policy != new.server_policy {
+            let _ = writeln!(out, "setServerPolicy({})", new.server_policy.name());
+        }
+        if self.acl != new.acl {
+            let _ = writeln!(out, "setACL({})", lua::string_array(&new.acl));
+        }
+        if self.render_dyn_blocks() != new.render_dyn_blocks() {
+            match &new.dyn_blocks {
+                Some(_) => out.push_str(&new.render_dyn_blocks()),
+                None => {
+                    let _ = writeln!(out, "function maintenance() end");
+                }
+            }
+        }
+        if self.render_rules() != new.render_rules() {
+            out.push_str(&new.render_rules());
+        }
+        Some(out)
+    }
+
+    /// Renders the config as a dnsdist lua file
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "-- generated by dnsdist-acme, do not edit");
+
+        let _ = writeln!(out, "\nfunction netmaskGroup(masks)");
+        let _ = writeln!(out, "    local nmg=newNMG()");
+        let _ = writeln!(
+            out,
+            "    for _, mask in ipairs(masks) do nmg:addMask(mask) end"
+        );
+        let _ = writeln!(out, "    return nmg");
+        let _ = writeln!(out, "end");
+
+        // used by reloads, to remove the backends that changed
+        let _ = writeln!(out, "\nfunction rmServerByName(name)");
+        let _ = writeln!(out, "    for _, server in ipairs(getServers()) do");
+        let _ = writeln!(
+            out,
+            "        if server:getName() == name then rmServer(server) end"
+        );
+        let _ = writeln!(out, "    end");
+        let _ = writeln!(out, "end");
+
+        let _ = writeln!(out, "\n-- backends");
+        out.push_str(&self.render_backends());
+        let _ = writeln!(out, "setServerPolicy({})", self.server_policy.name());
+
+        let _ = writeln!(out, "\n-- networks allowed to query");
+        let _ = writeln!(out, "setACL({})", lua::string_array(&self.acl));
+
+        let _ = writeln!(out, "\n-- listeners");
+        out.push_str(&self.render_listeners());
+
+        if self.console.is_some() {
+            let _ = writeln!(out, "\n-- console");
+            out.push_str(&self.render_console());
+        }
+
+        if self.dyn_blocks.is_some() {
+            let _ = writeln!(out, "\n-- dynamic blocks");
+            out.push_str(&self.render_dyn_blocks());
+        }
+
+        if self.dnstap.is_some() {
+            let _ = writeln!(out, "\n-- dnstap logging");
+            out.push_str(&self.render_dnstap());
+        }
+
+        let _ = writeln!(out, "\n-- rules");
+        out.push_str(&self.render_rules());
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{
+        Action, Backend, Console, DnsdistConfig, Dnstap, Listener, ListenerAcl, ListenerKind,
+        Route, Rule, Selector, ServerPolicy,
+    };
+    use crate::certs::CertificatePaths;
+    use crate::dnsdist::{Acl, Protocol};
+
+    #[test]
+    fn test_render() {
+        let certificates = vec![CertificatePaths {
+            cert: PathBuf::from("./certs/fullchain.pem"),
+            key: PathBuf::from("./certs/privkey.pem"),
+        }];
+        let config = DnsdistConfig {
+            listeners: vec![
+                Listener::new("0.0.0.0:53".parse().unwrap(), ListenerKind::Dns),
+                Listener::new("[::]:53".parse().unwrap(), ListenerKind::Dns),
+                Listener::new(
+                    "[::]:443".parse().unwrap(),
+                    ListenerKind::Doh {
+                        certificates: certificates.clone(),
+                        paths: vec!["/".to_string(), "/dns-query".to_string()],
+                    },
+                ),
+                Listener::new(
+                    "0.0.0.0:853".parse().unwrap(),
+                    ListenerKind::Dot { certificates },
+                ),
+            ],
+            backends: vec![
+                "192.168.1.1:53;health-check=up".parse().unwrap(),
+                "[2001:db8::1]:53;name=filtered;pool=adult;weight=2;order=1;check-interval=5;check-name=example.com".parse().unwrap(),
+            ],
+            server_policy: ServerPolicy::Wrandom,
+            acl: vec!["0.0.0.0/0".to_string(), "::/0".to_string()],
+            acls: vec![
+                ListenerAcl {
+                    protocol: Protocol::Dns,
+                    port: 53,
+                    acl: Acl {
+                        allow: vec!["10.0.0.0/8".parse().unwrap()],
+                        deny: vec!["10.0.0.1".parse().unwrap()],
+                    },
+                },
+                ListenerAcl {
+                    protocol: Protocol::Doh,
+                    port: 443,
+                    acl: Acl::default(),
+                },
+            ],
+            rules: vec![Rule {
+                name: None,
+                selector: Selector::MaxQpsIp {
+                    qps: 10,
+                    v4_prefix: 32,
+                    v6_prefix: 48,
+                },
+                action: Action::Drop,
+            }],
+            dyn_blocks: None,
+            routes: vec!["Corp.Example.;lan=adult".parse().unwrap()],
+            dnstap: Some(Dnstap {
+                identity: "dns".to_string(),
+                socket: PathBuf::from("./dnstap.sock"),
+            }),
+            console: Some(Console {
+                address: "127.0.0.1:5199".parse().unwrap(),
+                key: "secret'key".to_string(),
+            }),
+        };
+
+        let routes = config.routes_output();
+        assert_eq!(routes.routes[0].backends, vec!["[2001:db8::1]:53"]);
+        assert_eq!(routes.default_backends, vec!["192.168.1.1:53"]);
+
+        let output = config.render();
+        let expected = [
+            "newServer({ address='192.168.1.1:53', name='resolver1', healthCheckMode='up' })",
+            "newServer({ address='[2001:db8::1]:53', name='filtered', healthCheckMode='auto', weight=2, order=1, pool='adult', checkInterval=5, checkName='example.com' })",
+            "setServerPolicy(wrandom)",
+            "setACL({ '0.0.0.0/0', '::/0' })",
+            "setLocal('0.0.0.0:53', { reusePort=true })",
+            "addLocal('[::]:53', { reusePort=true })",
+            "addDOHLocal('[::]:443', { './certs/fullchain.pem' }, { './certs/privkey.pem' }, { '/', '/dns-query' }, { doTCP=true, reusePort=true, tcpFastOpenSize=0 })",
+            "addTLSLocal('0.0.0.0:853', { './certs/fullchain.pem' }, { './certs/privkey.pem' }, { reusePort=true })",
+            "controlSocket('127.0.0.1:5199')",
+            "setKey('secret\\'key')",
+            "dnstap=newFrameStreamUnixLogger('./dnstap.sock')",
+            "addResponseAction(AllRule(), DnstapLogResponseAction('dns', dnstap))",
+            "route1=newSuffixMatchNode()",
+            "route1:add({ 'corp.example.', 'lan.' })",
+            "setRules({",
+            "    newRuleAction(AndRule({ DSTPortRule(53), OrRule({ NotRule(NetmaskGroupRule(netmaskGroup({ '10.0.0.0/8' }))), NetmaskGroupRule(netmaskGroup({ '10.0.0.1' })) }) }), DropAction(), { name='acl-dns' }),",
+            "    newRuleAction(MaxQPSIPRule(10, 32, 48), DropAction()),",
+            "    newRuleAction(SuffixMatchNodeRule(route1), PoolAction('adult')),",
+        ];
+        for line in expected {
+            assert!(
+                output.lines().any(|l| l == line),
+                "missing {line}\n{output}"
+            );
+        }
+        assert!(!output.contains("acl-doh"), "open acls have no rule");
+
+        assert_eq!(config.reload_commands(&config).unwrap(), "");
+
+        let mut new_config = config.clone();
+        new_config.backends[0] = "192.168.1.2:53".parse().unwrap();
+        new_config.acls[1].acl.deny = vec!["192.0.2.0/24".parse().unwrap()];
+        let commands = config.reload_commands(&new_config).unwrap();
+        let commands: Vec<&str> = commands.lines().collect();
+        assert_eq!(commands[0], "rmServerByName('resolver1')");
+        assert_eq!(
+            commands[1],
+            "newServer({ address='192.168.1.2:53', name='resolver1', healthCheckMode='auto' })"
+        );
+        assert_eq!(commands[2], "route1=newSuffixMatchNode()");
+        assert!(commands.iter().any(|c| c.contains("name='acl-doh'")));
+
+        new_config.listeners.pop();
+        assert!(config.reload_commands(&new_config).is_none());
+    }
+
+    #[test]
+    fn test_parse_backend() {
+        assert_eq!(
+            "8.8.8.8:53".parse::<Backend>().unwrap(),
+            Backend::new("8.8.8.8:53".parse().unwrap())
+        );
+        assert!("8.8.8.8".parse::<Backend>().is_err());
+        assert!("8.8.8.8:53;weight=heavy".parse::<Backend>().is_err());
+        assert!("8.8.8.8:53;health-check=sometimes"
+            .parse::<Backend>()
+            .is_err());
+        assert!("8.8.8.8:53;colour=blue".parse::<Backend>().is_err());
+
+        let backend: Backend = "8.8.8.8:443;transport=doh;subject-name=dns.google"
+            .parse()
+            .unwrap();
+        assert!(backend.render(0).ends_with(
+            "tls='openssl', subjectName='dns.google', validateCertificates=true, dohPath='/dns-query' })"
+        ));
+        assert!("8.8.8.8:853;transport=dot".parse::<Backend>().is_err());
+        assert!("8.8.8.8:853;transport=dot;validate-certificates=false"
+            .parse::<Backend>()
+            .is_ok());
+        assert!("8.8.8.8:53;subject-name=dns.google"
+            .parse::<Backend>()
+            .is_err());
+        assert!(
+            "8.8.8.8:853;transport=dot;subject-name=dns.google;doh-path=/q"
+                .parse::<Backend>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_route() {
+        let route: Route = "corp.example=internal".parse().unwrap();
+        assert_eq!(route.suffixes, vec!["corp.example."]);
+        assert_eq!(route.pool, "internal");
+        assert!("corp.example".parse::<Route>().is_err());
+        assert!("corp.example=".parse::<Route>().is_err());
+        assert!("corp..example=internal".parse::<Route>().is_err());
+        assert!(";lan=internal".parse::<Route>().is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use base64::Engine;
+use crypto_secretbox::{
+    aead::{Aead, KeyInit},
+    XSalsa20Poly1305,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+
+/// Responses larger than this are assumed to be garbage rather than console output
+const MAX_RESPONSE_LEN: usize = 16 * 1024 * 1024;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+type Nonce = [u8; NONCE_LEN];
+
+/// Talks to the console of the running dnsdist. The key is not printed by Debug.
+#[derive(Clone)]
+pub struct DnsdistConsole {
+    /// Changes when a restarted dnsdist takes over on the other console port
+    address: Arc<Mutex<SocketAddr>>,
+    key: String,
+}
+
+impl std::fmt::Debug for DnsdistConsole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DnsdistConsole")
+            .field("address", &self.address())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Generates a random console key, in the base64 format of the dnsdist `makeKey()` command
+pub fn generate_console_key() -> String {
+    let key: [u8; KEY_LEN] = rand::random();
+    base64::engine::general_purpose::STANDARD.encode(key)
+}
+
+fn decode_key(key: &str) -> Result<[u8; KEY_LEN], anyhow::Error> {
+    let decoded = base64::engine::general_purpose::STANDARD.decode(key)?;
+    match decoded.try_into() {
+        Ok(key) => Ok(key),
+        Err(_) => bail!("the console key must be {KEY_LEN} bytes in base64"),
+    }
+}
+
+/// Loads a console key from a file, e.g. a mounted secret
+pub async fn load_console_key(path: &Path) -> Result<String, anyhow::Error> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("fail to read console key file {}", path.display()))?;
+    let key = content.trim().to_string();
+    decode_key(&key).with_context(|| format!("invalid console key in {}", path.display()))?;
+
+    Ok(key)
+}
+
+/// Takes the first half of the nonce from `lower` and the second half from `higher`
+fn merge_nonces(lower: &Nonce, higher: &Nonce) -> Nonce {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..NONCE_LEN / 2].copy_from_slice(&lower[..NONCE_LEN / 2]);
+    nonce[NONCE_LEN / 2..].copy_from_slice(&higher[NONCE_LEN / 2..]);
+    nonce
+}
+
+/// Increments the counter in the first 4 bytes of the nonce, after every message
+fn increment_nonce(nonce: &mut Nonce) {
+    let count = u32::from_be_bytes([nonce[0], nonce[1], nonce[2], nonce[3]]);
+    nonce[..4].copy_from_slice(&count.wrapping_add(1).to_be_bytes());
+}
+
+/// A session with the console. Both sides send a random nonce when connecting, the nonces of
+/// each direction are merged from them. Every message is a 32 bit big endian length followed by
+/// a secretbox, and the nonce of its direction is incremented after it.
+struct ConsoleConnection {
+    stream: TcpStream,
+    cipher: XSalsa20Poly1305,
+    reading_nonce: Nonce,
+    writing_nonce: Nonce,
+}
+
+impl ConsoleConnection {
+    async fn connect(address: SocketAddr, key: &[u8; KEY_LEN]) -> Result<Self, anyhow::Error> {
+        let mut stream = TcpStream::connect(address)
+            .await
+            .with_context(|| format!("fail to connect to the dnsdist console at {address}"))?;
+
+        let ours: Nonce = rand::random();
+        stream.write_all(&ours).await?;
+        let mut theirs: Nonce = [0; NONCE_LEN];
+        stream.read_exact(&mut theirs).await?;
+
+        Ok(Self {
+            stream,
+            cipher: XSalsa20Poly1305::new(key.into()),
+            reading_nonce: merge_nonces(&ours, &theirs),
+            writing_nonce: merge_nonces(&theirs, &ours),
+        })
+    }
+
+    async fn execute(&mut self, command: &str) -> Result<String, anyhow::Error> {
+        let message = self
+            .cipher
+            .encrypt((&self.writing_nonce).into(), command.as_bytes())
+            .map_err(|_| anyhow::anyhow!("fail to encrypt the console command"))?;
+        increment_nonce(&mut self.writing_nonce);
+
+        let len = u32::try_from(message.len()).context("console command is too long")?;
+        self.stream.write_all(&len.to_be_bytes()).await?;
+        self.stream.write_all(&message).await?;
+
+        let mut len = [0; 4];
+        self.stream
+            .read_exact(&mut len)
+            .await
+            .context("dnsdist closed the console connection, the console key is probably wrong")?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 {
+            return Ok(String::new());
+        }
+        if len > MAX_RESPONSE_LEN {
+            bail!("console response of {len} bytes is too long");
+        }
+
+        let mut message = vec![0; len];
+        self.stream.read_exact(&mut message).await?;
+        let response = self
+            .cipher
+            .decrypt((&self.reading_nonce).into(), message.as_slice())
+            .map_err(|_| anyhow::anyhow!("fail to decrypt the console response"))?;
+        increment_nonce(&mut self.reading_nonce);
+
+        Ok(String::from_utf8_lossy(&response).into_owned())
+    }
+}
+
+impl DnsdistConsole {
+    pub fn new(address: SocketAddr, key: String) -> Self {
+        Self {
+            address: Arc::new(Mutex::new(address)),
+            key,
+        }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        *self.address.lock().unwrap()
+    }
+
+    pub fn set_address(&self, address: SocketAddr) {
+        *self.address.lock().unwrap() = address;
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Runs lua commands on the console, and returns what they printed. Lua errors, which
+    /// dnsdist prints as `Error: ...`, are returned as errors.
+    pub async fn run(&self, commands: &str) -> Result<String, anyhow::Error> {
+        let key = decode_key(&self.key)?;
+        let response = tokio::time::timeout(TIMEOUT, async {
+            let mut connection = ConsoleConnection::connect(self.address(), &key).await?;
+            connection.execute(commands).await
+        })
+        .await
+        .context("dnsdist console timed out")??;
+
+        if response.starts_with("Error: ") {
+            bail!("dnsdist console: {}", response.trim());
+        }
+
+        Ok(response)
+    }
+
+    pub async fn reload_certificates(&self) -> Result<(), anyhow::Error> {
+        self.run("reloadAllCertificates()").await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crypto_secretbox::{
+        aead::{Aead, KeyInit},
+        XSalsa20Poly1305,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{
+        decode_key, generate_console_key, increment_nonce, merge_nonces, DnsdistConsole, Nonce,
+    };
+
+    /// Serves one console session the way dnsdist does, answering every command with `respond`
+    async fn serve_console(listener: TcpListener, key: String, respond: fn(&str) -> String) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let cipher = XSalsa20Poly1305::new((&decode_key(&key).unwrap()).into());
+
+        let mut theirs: Nonce = [0; 24];
+        stream.read_exact(&mut theirs).await.unwrap();
+        let ours: Nonce = rand::random();
+        stream.write_all(&ours).await.unwrap();
+        let mut reading_nonce = merge_nonces(&ours, &theirs);
+        let mut writing_nonce = merge_nonces(&theirs, &ours);
+
+        let mut len = [0; 4];
+        while stream.read_exact(&mut len).await.is_ok() {
+            let mut message = vec![0; u32::from_be_bytes(len) as usize];
+            stream.read_exact(&mut message).await.unwrap();
+            let command = cipher
+                .decrypt((&reading_nonce).into(), message.as_slice())
+                .unwrap();
+            increment_nonce(&mut reading_nonce);
+
+            let response = respond(&String::from_utf8(command).unwrap());
+            let message = cipher
+                .encrypt((&writing_nonce).into(), response.as_bytes())
+                .unwrap();
+            increment_nonce(&mut writing_nonce);
+            stream
+                .write_all(&(message.len() as u32).to_be_bytes())
+                .await
+                .unwrap();
+            stream.write_all(&message).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_console_run() {
+        let mut nonce = [0xff; 24];
+        nonce[3] = 0xfe;
+        increment_nonce(&mut nonce);
+        assert_eq!(nonce[..4], [0xff; 4]);
+
+        let key = generate_console_key();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let console = DnsdistConsole::new(listener.local_addr().unwrap(), key.clone());
+        tokio::spawn(serve_console(listener, key, |command| match command {
+            "showVersion()" => "dnsdist 1.8.3\n".to_string(),
+            _ => "Error: [string \"chunk\"]:1: syntax error\n".to_string(),
+        }));
+        assert_eq!(
+            console.run("showVersion()").await.unwrap(),
+            "dnsdist 1.8.3\n"
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let console = DnsdistConsole::new(listener.local_addr().unwrap(), generate_console_key());
+        tokio::spawn(serve_console(listener, generate_console_key(), |_| {
+            String::new()
+        }));
+        assert!(console.run("showVersion()").await.is_err(), "wrong key");
+    }
+}
@@ -0,0 +1,208 @@
+use std::{fmt::Write, str::FromStr};
+
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+
+use super::{lua, Action};
+
+/// What is done with the queries of a client over a limit
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitAction {
+    /// Sends no response
+    Drop,
+    /// Responds with the TC bit set, so real clients retry over TCP
+    Truncate,
+    /// Responds with REFUSED
+    Refused,
+}
+
+impl LimitAction {
+    pub fn action(&self) -> Action {
+        match self {
+            LimitAction::Drop => Action::Drop,
+            LimitAction::Truncate => Action::Truncate,
+            LimitAction::Refused => Action::Refused,
+        }
+    }
+
+    fn dns_action(&self) -> &'static str {
+        match self {
+            LimitAction::Drop => "DNSAction.Drop",
+            LimitAction::Truncate => "DNSAction.Truncate",
+            LimitAction::Refused => "DNSAction.Refused",
+        }
+    }
+}
+
+/// A queries per second limit, over the clients of a network. Parsed from `QPS[;key=value...]`,
+/// e.g. `50;v4-prefix=24;v6-prefix=48;action=truncate`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    pub qps: u32,
+    pub v4_prefix: u8,
+    pub v6_prefix: u8,
+    pub action: LimitAction,
+}
+
+impl FromStr for RateLimit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(';').map(str::trim);
+        let qps = parts.next().unwrap_or_default();
+        let qps = qps
+            .parse()
+            .with_context(|| format!("invalid rate limit qps {qps:?}"))?;
+
+        let mut limit = RateLimit {
+            qps,
+            v4_prefix: 32,
+            v6_prefix: 48,
+            action: LimitAction::Drop,
+        };
+        for part in parts.filter(|p| !p.is_empty()) {
+            let Some((key, value)) = part.split_once('=') else {
+                bail!("invalid rate limit option {part:?}, expected key=value");
+            };
+            let invalid = || format!("invalid rate limit option {part:?}");
+            match key.trim() {
+                "v4-prefix" => limit.v4_prefix = value.parse().with_context(invalid)?,
+                "v6-prefix" => limit.v6_prefix = value.parse().with_context(invalid)?,
+                "action" => {
+                    limit.action = LimitAction::from_str(value, true).map_err(anyhow::Error::msg)?
+                }
+                _ => bail!("unknown rate limit option {key:?}"),
+            }
+        }
+
+        if limit.v4_prefix > 32 || limit.v6_prefix > 128 {
+            bail!("invalid rate limit {s:?}, the prefix lengths are at most 32 and 128");
+        }
+
+        Ok(limit)
+    }
+}
+
+/// What a dynamic block rule measures the rate of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DynBlockTrigger {
+    /// Queries per second
+    Queries,
+    /// NXDOMAIN responses per second
+    Nxdomains,
+    /// SERVFAIL responses per second
+    Servfails,
+    /// Response bytes per second
+    ResponseBytes,
+}
+
+/// Blocks a client for `duration` seconds, once its rate over the last `seconds` is above `rate`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynBlockRule {
+    pub trigger: DynBlockTrigger,
+    pub rate: u32,
+    pub seconds: u32,
+    pub duration: u32,
+}
+
+/// Dynamic block rules, they are checked by the dnsdist maintenance function every second
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynBlocks {
+    pub rules: Vec<DynBlockRule>,
+    /// Clients are grouped into networks by the prefix lengths, and blocked together
+    pub v4_prefix: u8,
+    pub v6_prefix: u8,
+    pub action: LimitAction,
+}
+
+impl DynBlockRule {
+    fn render(&self) -> String {
+        let Self {
+            rate,
+            seconds,
+            duration,
+            ..
+        } = self;
+        match self.trigger {
+            DynBlockTrigger::Queries => {
+                let reason = lua::string("Exceeded query rate");
+                format!("setQueryRate({rate}, {seconds}, {reason}, {duration})")
+            }
+            DynBlockTrigger::Nxdomains => {
+                let reason = lua::string("Exceeded NXDOMAIN rate");
+                format!("setRCodeRate(DNSRCode.NXDOMAIN, {rate}, {seconds}, {reason}, {duration})")
+            }
+            DynBlockTrigger::Servfails => {
+                let reason = lua::string("Exceeded SERVFAIL rate");
+                format!("setRCodeRate(DNSRCode.SERVFAIL, {rate}, {seconds}, {reason}, {duration})")
+            }
+            DynBlockTrigger::ResponseBytes => {
+                let reason = lua::string("Exceeded response byte rate");
+                format!("setResponseByteRate({rate}, {seconds}, {reason}, {duration})")
+            }
+        }
+    }
+}
+
+impl DynBlocks {
+    pub fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "setDynBlocksAction({})", self.action.dns_action());
+        let _ = writeln!(out, "dynBlocks=dynBlockRulesGroup()");
+        let _ = writeln!(
+            out,
+            "dynBlocks:setMasks({}, {}, 0)",
+            self.v4_prefix, self.v6_prefix
+        );
+        for rule in self.rules.iter() {
+            let _ = writeln!(out, "dynBlocks:{}", rule.render());
+        }
+        let _ = writeln!(out, "function maintenance()");
+        let _ = writeln!(out, "    dynBlocks:apply()");
+        let _ = writeln!(out, "end");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DynBlockRule, DynBlockTrigger, DynBlocks, LimitAction, RateLimit};
+
+    #[test]
+    fn test_limits() {
+        assert_eq!(
+            "50;v4-prefix=24;action=truncate"
+                .parse::<RateLimit>()
+                .unwrap(),
+            RateLimit {
+                qps: 50,
+                v4_prefix: 24,
+                v6_prefix: 48,
+                action: LimitAction::Truncate,
+            }
+        );
+        assert!("fast".parse::<RateLimit>().is_err());
+        assert!("10;v4-prefix=33".parse::<RateLimit>().is_err());
+        assert!("10;action=ignore".parse::<RateLimit>().is_err());
+
+        let dyn_blocks = DynBlocks {
+            rules: vec![DynBlockRule {
+                trigger: DynBlockTrigger::Nxdomains,
+                rate: 20,
+                seconds: 10,
+                duration: 60,
+            }],
+            v4_prefix: 32,
+            v6_prefix: 64,
+            action: LimitAction::Refused,
+        };
+        let mut output = String::new();
+        dyn_blocks.render(&mut output);
+        assert_eq!(
+            output,
+            "setDynBlocksAction(DNSAction.Refused)\n\
+             dynBlocks=dynBlockRulesGroup()\n\
+             dynBlocks:setMasks(32, 64, 0)\n\
+             dynBlocks:setRCodeRate(DNSRCode.NXDOMAIN, 20, 10, 'Exceeded NXDOMAIN rate', 60)\n\
+             function maintenance()\n    dynBlocks:apply()\nend\n"
+        );
+    }
+}
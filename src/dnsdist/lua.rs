@@ -0,0 +1,48 @@
+/// Quotes a string as a lua string literal
+pub fn string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '\'' => quoted.push_str("\\'"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\0' => quoted.push_str("\\0"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Renders a lua array of strings, e.g. `{ 'a', 'b' }`
+pub fn string_array<S: AsRef<str>>(values: &[S]) -> String {
+    let values: Vec<String> = values.iter().map(|v| string(v.as_ref())).collect();
+    format!("{{ {} }}", values.join(", "))
+}
+
+/// Renders a lua table of already rendered values, e.g. `{ name='a', weight=1 }`
+pub fn table(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields.iter().map(|(k, v)| format!("{k}={v}")).collect();
+    format!("{{ {} }}", fields.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_lua_values() {
+        assert_eq!(super::string("it's a \\ test\n"), r"'it\'s a \\ test\n'");
+        assert_eq!(
+            super::string_array(&["/", "/dns-query"]),
+            "{ '/', '/dns-query' }"
+        );
+        assert_eq!(
+            super::table(&[
+                ("reusePort", "true".to_string()),
+                ("name", super::string("a"))
+            ]),
+            "{ reusePort=true, name='a' }"
+        );
+    }
+}
@@ -0,0 +1,12 @@
+pub mod acl;
+pub mod blocks;
+pub mod config;
+pub mod console;
+pub mod limits;
+pub mod lua;
+
+pub use acl::*;
+pub use blocks::*;
+pub use config::*;
+pub use console::*;
+pub use limits::*;
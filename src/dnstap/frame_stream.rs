@@ -0,0 +1,265 @@
+use anyhow::{bail, Context};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// Frame Streams protocol, as spoken by dnsdist's FrameStreamUnixLogger
+// https://farsightsec.github.io/fstrm/
+//
+// A frame is a big-endian u32 length followed by that many bytes.
+// A length of 0 escapes a control frame, which is another u32 length,
+// a u32 control type, and a list of (u32 field type, u32 length, bytes) fields.
+
+pub const DNSTAP_CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+
+const CONTROL_ACCEPT: u32 = 0x01;
+const CONTROL_START: u32 = 0x02;
+const CONTROL_STOP: u32 = 0x03;
+const CONTROL_READY: u32 = 0x04;
+const CONTROL_FINISH: u32 = 0x05;
+
+const CONTROL_FIELD_CONTENT_TYPE: u32 = 0x01;
+
+const MAX_CONTROL_FRAME_LENGTH: u32 = 512;
+const MAX_DATA_FRAME_LENGTH: u32 = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+struct ControlFrame {
+    control_type: u32,
+    content_types: Vec<Vec<u8>>,
+}
+
+impl ControlFrame {
+    fn new(control_type: u32, content_type: Option<&[u8]>) -> Self {
+        let content_types = content_type.map(|c| vec![c.to_vec()]).unwrap_or_default();
+        Self {
+            control_type,
+            content_types,
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut words = bytes;
+        let control_type = take_u32(&mut words).context("control frame is missing its type")?;
+
+        let mut content_types = Vec::new();
+        while !words.is_empty() {
+            let field_type = take_u32(&mut words).context("truncated control field type")?;
+            let field_len = take_u32(&mut words).context("truncated control field length")?;
+            let field_len = field_len as usize;
+            if words.len() < field_len {
+                bail!("truncated control field value");
+            }
+
+            let (value, rest) = words.split_at(field_len);
+            if field_type == CONTROL_FIELD_CONTENT_TYPE {
+                content_types.push(value.to_vec());
+            }
+            words = rest;
+        }
+
+        Ok(Self {
+            control_type,
+            content_types,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut payload = self.control_type.to_be_bytes().to_vec();
+        for content_type in self.content_types.iter() {
+            payload.extend(CONTROL_FIELD_CONTENT_TYPE.to_be_bytes());
+            payload.extend((content_type.len() as u32).to_be_bytes());
+            payload.extend(content_type);
+        }
+
+        let mut frame = 0u32.to_be_bytes().to_vec();
+        frame.extend((payload.len() as u32).to_be_bytes());
+        frame.extend(payload);
+        frame
+    }
+}
+
+fn take_u32(bytes: &mut &[u8]) -> Option<u32> {
+    if bytes.len() < 4 {
+        return None;
+    }
+    let (word, rest) = bytes.split_at(4);
+    *bytes = rest;
+    Some(u32::from_be_bytes(word.try_into().ok()?))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Frame {
+    Data(Vec<u8>),
+    Control(ControlFrame),
+}
+
+pub struct FrameStreamReader<S> {
+    stream: S,
+    content_type: Vec<u8>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> FrameStreamReader<S> {
+    /// Performs the reader side of the bidirectional handshake (READY -> ACCEPT -> START).
+    /// Unidirectional writers that skip straight to START are accepted as well.
+    pub async fn accept(stream: S, content_type: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut reader = Self {
+            stream,
+            content_type: content_type.to_vec(),
+        };
+
+        let mut control = reader.read_control().await?;
+        if control.control_type == CONTROL_READY {
+            reader.check_content_type(&control)?;
+
+            let accept = ControlFrame::new(CONTROL_ACCEPT, Some(content_type));
+            reader.stream.write_all(&accept.encode()).await?;
+            reader.stream.flush().await?;
+
+            control = reader.read_control().await?;
+        }
+
+        if control.control_type != CONTROL_START {
+            bail!(
+                "expected START frame, got control type {}",
+                control.control_type
+            );
+        }
+        reader.check_content_type(&control)?;
+
+        Ok(reader)
+    }
+
+    /// Returns the next data frame, or None once the writer has stopped.
+    pub async fn next_frame(&mut self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        loop {
+            let frame = match self.read_frame().await? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+
+            match frame {
+                Frame::Data(data) => return Ok(Some(data)),
+                Frame::Control(control) if control.control_type == CONTROL_STOP => {
+                    let finish = ControlFrame::new(CONTROL_FINISH, None);
+                    self.stream.write_all(&finish.encode()).await?;
+                    self.stream.flush().await?;
+                    return Ok(None);
+                }
+                Frame::Control(control) => {
+                    tracing::debug!(
+                        "frame stream ignoring control type {}",
+                        control.control_type
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_content_type(&self, control: &ControlFrame) -> Result<(), anyhow::Error> {
+        if control.content_types.is_empty() || control.content_types.contains(&self.content_type) {
+            return Ok(());
+        }
+
+        bail!(
+            "frame stream writer does not offer content type {:?}",
+            String::from_utf8_lossy(&self.content_type)
+        )
+    }
+
+    async fn read_control(&mut self) -> Result<ControlFrame, anyhow::Error> {
+        match self.read_frame().await? {
+            Some(Frame::Control(control)) => Ok(control),
+            Some(Frame::Data(_)) => bail!("expected control frame, got data frame"),
+            None => bail!("frame stream closed during handshake"),
+        }
+    }
+
+    async fn read_frame(&mut self) -> Result<Option<Frame>, anyhow::Error> {
+        let len = match self.stream.read_u32().await {
+            Ok(len) => len,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        if len == 0 {
+            let len = self.stream.read_u32().await?;
+            if len > MAX_CONTROL_FRAME_LENGTH {
+                bail!("control frame too large: {len} bytes");
+            }
+
+            let mut buf = vec![0; len as usize];
+            self.stream.read_exact(&mut buf).await?;
+            return Ok(Some(Frame::Control(ControlFrame::decode(&buf)?)));
+        }
+
+        if len > MAX_DATA_FRAME_LENGTH {
+            bail!("data frame too large: {len} bytes");
+        }
+
+        let mut buf = vec![0; len as usize];
+        self.stream.read_exact(&mut buf).await?;
+        Ok(Some(Frame::Data(buf)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{
+        ControlFrame, FrameStreamReader, CONTROL_ACCEPT, CONTROL_FINISH, CONTROL_READY,
+        CONTROL_START, CONTROL_STOP, DNSTAP_CONTENT_TYPE,
+    };
+
+    #[test]
+    fn test_control_frame_roundtrip() {
+        let frame = ControlFrame::new(CONTROL_READY, Some(DNSTAP_CONTENT_TYPE));
+        let encoded = frame.encode();
+
+        assert_eq!(&encoded[0..4], &[0, 0, 0, 0]);
+        let output = ControlFrame::decode(&encoded[8..]).unwrap();
+        assert_eq!(output, frame);
+    }
+
+    #[tokio::test]
+    async fn test_bidirectional_handshake() {
+        let (mut writer, reader) = tokio::io::duplex(4096);
+
+        let handle = tokio::spawn(async move {
+            let mut reader = FrameStreamReader::accept(reader, DNSTAP_CONTENT_TYPE)
+                .await
+                .unwrap();
+            let mut frames = Vec::new();
+            while let Some(frame) = reader.next_frame().await.unwrap() {
+                frames.push(frame);
+            }
+            frames
+        });
+
+        let ready = ControlFrame::new(CONTROL_READY, Some(DNSTAP_CONTENT_TYPE));
+        writer.write_all(&ready.encode()).await.unwrap();
+
+        let mut accept = vec![0; ready.encode().len()];
+        writer.read_exact(&mut accept).await.unwrap();
+        let accept = ControlFrame::decode(&accept[8..]).unwrap();
+        assert_eq!(
+            accept,
+            ControlFrame::new(CONTROL_ACCEPT, Some(DNSTAP_CONTENT_TYPE))
+        );
+
+        let start = ControlFrame::new(CONTROL_START, Some(DNSTAP_CONTENT_TYPE));
+        writer.write_all(&start.encode()).await.unwrap();
+        writer.write_all(&3u32.to_be_bytes()).await.unwrap();
+        writer.write_all(b"abc").await.unwrap();
+        writer
+            .write_all(&ControlFrame::new(CONTROL_STOP, None).encode())
+            .await
+            .unwrap();
+
+        let mut finish = vec![0; 12];
+        writer.read_exact(&mut finish).await.unwrap();
+        let finish = ControlFrame::decode(&finish[8..]).unwrap();
+        assert_eq!(finish, ControlFrame::new(CONTROL_FINISH, None));
+
+        assert_eq!(handle.await.unwrap(), vec![b"abc".to_vec()]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// Subset of https://github.com/dnstap/dnstap.pb/blob/master/dnstap.proto
+// Only the fields we read are declared, prost skips over the rest.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Dnstap {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    pub identity: Option<Vec<u8>>,
+
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub version: Option<Vec<u8>>,
+
+    #[prost(enumeration = "DnstapType", required, tag = "15")]
+    pub r#type: i32,
+
+    #[prost(message, optional, tag = "14")]
+    pub message: Option<Message>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum DnstapType {
+    Message = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum SocketFamily {
+    Inet = 1,
+    Inet6 = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum SocketProtocol {
+    Udp = 1,
+    Tcp = 2,
+    Dot = 3,
+    Doh = 4,
+    DnsCryptUdp = 5,
+    DnsCryptTcp = 6,
+    Doq = 7,
+}
+
+impl SocketProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SocketProtocol::Udp => "UDP",
+            SocketProtocol::Tcp => "TCP",
+            SocketProtocol::Dot => "DoT",
+            SocketProtocol::Doh => "DoH",
+            SocketProtocol::DnsCryptUdp => "DNSCrypt-UDP",
+            SocketProtocol::DnsCryptTcp => "DNSCrypt-TCP",
+            SocketProtocol::Doq => "DoQ",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum MessageType {
+    AuthQuery = 1,
+    AuthResponse = 2,
+    ResolverQuery = 3,
+    ResolverResponse = 4,
+    ClientQuery = 5,
+    ClientResponse = 6,
+    ForwarderQuery = 7,
+    ForwarderResponse = 8,
+    StubQuery = 9,
+    StubResponse = 10,
+    ToolQuery = 11,
+    ToolResponse = 12,
+    UpdateQuery = 13,
+    UpdateResponse = 14,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Message {
+    #[prost(enumeration = "MessageType", required, tag = "1")]
+    pub r#type: i32,
+
+    #[prost(enumeration = "SocketFamily", optional, tag = "2")]
+    pub socket_family: Option<i32>,
+
+    #[prost(enumeration = "SocketProtocol", optional, tag = "3")]
+    pub socket_protocol: Option<i32>,
+
+    #[prost(bytes = "vec", optional, tag = "4")]
+    pub query_address: Option<Vec<u8>>,
+
+    #[prost(bytes = "vec", optional, tag = "5")]
+    pub response_address: Option<Vec<u8>>,
+
+    #[prost(uint32, optional, tag = "6")]
+    pub query_port: Option<u32>,
+
+    #[prost(uint32, optional, tag = "7")]
+    pub response_port: Option<u32>,
+
+    #[prost(uint64, optional, tag = "8")]
+    pub query_time_sec: Option<u64>,
+
+    #[prost(fixed32, optional, tag = "9")]
+    pub query_time_nsec: Option<u32>,
+
+    #[prost(bytes = "vec", optional, tag = "10")]
+    pub query_message: Option<Vec<u8>>,
+
+    #[prost(uint64, optional, tag = "12")]
+    pub response_time_sec: Option<u64>,
+
+    #[prost(fixed32, optional, tag = "13")]
+    pub response_time_nsec: Option<u32>,
+
+    #[prost(bytes = "vec", optional, tag = "14")]
+    pub response_message: Option<Vec<u8>>,
+}
+
+pub fn parse_address(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => {
+            let octets: [u8; 4] = bytes.try_into().ok()?;
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        16 => {
+            let octets: [u8; 16] = bytes.try_into().ok()?;
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
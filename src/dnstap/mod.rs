@@ -0,0 +1,5 @@
+mod frame_stream;
+mod message;
+
+pub use frame_stream::*;
+pub use message::*;
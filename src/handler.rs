@@ -1,13 +1,20 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
-    extract::{ConnectInfo, State},
-    response::Html,
+    extract::{ConnectInfo, Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
     Json,
 };
 use handlebars::Handlebars;
 
+use crate::acme::Http01Challenges;
+use crate::dnsdist::{parse_dyn_blocks, BlockedClient, RoutesOutput, SHOW_DYN_BLOCKS};
 use crate::logs::{QueryLog, QueryLogs, UsageStats};
+use crate::metrics::render_metrics;
+use crate::tasks::dnsdist::{DnsdistReloader, ReloadOutcome, ReloadTrigger};
+use crate::tasks::supervisor::{ProcessStatus, SupervisorStatus};
+use crate::tls::{TlsStatus, TlsStatusOutput};
 
 static GET_LOGS_TEMPLATE: &str = include_str!("./get_logs.hbs");
 
@@ -22,19 +29,51 @@ pub struct GetLogsOutput {
     ip: String,
     queries: Vec<QueryLog>,
     active_ips: usize,
+    invalid_logs: usize,
 }
 
 #[derive(Clone)]
 pub struct AppState {
     logs_store: QueryLogs,
     usage_stats: UsageStats,
+    challenges: Http01Challenges,
+    tls_status: TlsStatus,
+    reloader: DnsdistReloader,
+    supervisor_status: SupervisorStatus,
+}
+
+/// The state of the admin api, which is served on its own address
+#[derive(Clone)]
+pub struct AdminApi {
+    token: Arc<String>,
+    reload_trigger: ReloadTrigger,
+}
+
+impl AdminApi {
+    pub fn new(token: String, reload_trigger: ReloadTrigger) -> Self {
+        Self {
+            token: Arc::new(token),
+            reload_trigger,
+        }
+    }
 }
 
 impl AppState {
-    pub fn new(logs_store: QueryLogs, usage_stats: UsageStats) -> Self {
+    pub fn new(
+        logs_store: QueryLogs,
+        usage_stats: UsageStats,
+        challenges: Http01Challenges,
+        tls_status: TlsStatus,
+        reloader: DnsdistReloader,
+        supervisor_status: SupervisorStatus,
+    ) -> Self {
         Self {
             logs_store,
             usage_stats,
+            challenges,
+            tls_status,
+            reloader,
+            supervisor_status,
         }
     }
 }
@@ -70,6 +109,7 @@ pub async fn get_logs(
     let ip = get_ip(addr);
     let queries = app_state.logs_store.get_logs_for_ip(&ip);
     let active_ips = app_state.usage_stats.get_active_ips();
+    let invalid_logs = app_state.usage_stats.get_invalid_logs();
 
     let reg = Handlebars::new();
     let response = reg
@@ -79,9 +119,114 @@ pub async fn get_logs(
                 ip,
                 queries,
                 active_ips,
+                invalid_logs,
             },
         )
         .unwrap();
 
     Html(response)
 }
+
+#[axum_macros::debug_handler]
+pub async fn get_acme_challenge(
+    Path(token): Path<String>,
+    State(app_state): State<AppState>,
+) -> Result<String, StatusCode> {
+    tracing::info!("get_acme_challenge - token: {token}");
+
+    app_state
+        .challenges
+        .get(&token)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[axum_macros::debug_handler]
+pub async fn get_tls_api(State(app_state): State<AppState>) -> Json<TlsStatusOutput> {
+    tracing::info!("get_tls_api");
+
+    Json(app_state.tls_status.get().await)
+}
+
+#[axum_macros::debug_handler]
+pub async fn get_routes_api(State(app_state): State<AppState>) -> Json<RoutesOutput> {
+    tracing::info!("get_routes_api");
+
+    Json(app_state.reloader.config().await.routes_output())
+}
+
+#[axum_macros::debug_handler]
+pub async fn get_blocks_api(
+    State(app_state): State<AppState>,
+) -> Result<Json<Vec<BlockedClient>>, (StatusCode, String)> {
+    tracing::info!("get_blocks_api");
+
+    match app_state.reloader.console().run(SHOW_DYN_BLOCKS).await {
+        Ok(output) => Ok(Json(parse_dyn_blocks(&output, chrono::Utc::now()))),
+        Err(err) => {
+            tracing::error!("get_blocks_api. ERROR: {err:#}");
+            Err((
+                StatusCode::BAD_GATEWAY,
+                "fail to read the blocks from dnsdist".to_string(),
+            ))
+        }
+    }
+}
+
+#[axum_macros::debug_handler]
+pub async fn get_processes_api(State(app_state): State<AppState>) -> Json<Vec<ProcessStatus>> {
+    tracing::info!("get_processes_api");
+
+    Json(app_state.supervisor_status.get())
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct ReloadOutput {
+    outcome: ReloadOutcome,
+}
+
+/// Compares the tokens without returning early, so the time taken does not tell how much of
+/// a guess was right
+fn token_matches(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[axum_macros::debug_handler]
+pub async fn post_reload_api(
+    State(admin): State<AdminApi>,
+    headers: HeaderMap,
+) -> Result<Json<ReloadOutput>, (StatusCode, String)> {
+    tracing::info!("post_reload_api");
+
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !given.is_some_and(|given| token_matches(given, &admin.token)) {
+        return Err((StatusCode::UNAUTHORIZED, "invalid admin token".to_string()));
+    }
+
+    match admin.reload_trigger.reload().await {
+        Ok(outcome) => Ok(Json(ReloadOutput { outcome })),
+        Err(err) => {
+            tracing::error!("post_reload_api. ERROR: {err:#}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")))
+        }
+    }
+}
+
+#[axum_macros::debug_handler]
+pub async fn get_metrics(State(app_state): State<AppState>) -> impl IntoResponse {
+    let tls = app_state.tls_status.get().await;
+    let active_ips = app_state.usage_stats.get_active_ips();
+    let invalid_logs = app_state.usage_stats.get_invalid_logs();
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render_metrics(&tls, active_ips, invalid_logs),
+    )
+}
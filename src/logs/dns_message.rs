@@ -0,0 +1,110 @@
+use hickory_proto::{
+    op::{Message, ResponseCode},
+    rr::Record,
+};
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Default)]
+pub struct DnsFlags {
+    pub qr: bool,
+    pub aa: bool,
+    pub tc: bool,
+    pub rd: bool,
+    pub ra: bool,
+    pub ad: bool,
+    pub cd: bool,
+}
+
+impl DnsFlags {
+    fn from_message(message: &Message) -> Self {
+        let header = message.header();
+        Self {
+            qr: message.message_type() == hickory_proto::op::MessageType::Response,
+            aa: header.authoritative(),
+            tc: header.truncated(),
+            rd: header.recursion_desired(),
+            ra: header.recursion_available(),
+            ad: header.authentic_data(),
+            cd: header.checking_disabled(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct DnsRecord {
+    pub name: String,
+    pub ttl: u32,
+    #[serde(rename = "type")]
+    pub rtype: String,
+    pub rdata: String,
+}
+
+impl From<&Record> for DnsRecord {
+    fn from(record: &Record) -> Self {
+        Self {
+            name: record.name().to_string(),
+            ttl: record.ttl(),
+            rtype: record.record_type().to_string(),
+            rdata: record.data().map(|d| d.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+/// The parts of a dns response message that we keep in the query logs
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct DnsResponse {
+    pub qname: String,
+    pub qtype: String,
+    pub qclass: String,
+    pub rcode: String,
+    pub flags: DnsFlags,
+    pub answers: Vec<DnsRecord>,
+    pub authority: Vec<DnsRecord>,
+    pub additional: Vec<DnsRecord>,
+}
+
+impl DnsResponse {
+    pub fn parse(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        let message = Message::from_vec(bytes)?;
+
+        let (qname, qtype, qclass) = match message.queries().first() {
+            Some(q) => (
+                q.name().to_string(),
+                q.query_type().to_string(),
+                q.query_class().to_string(),
+            ),
+            None => Default::default(),
+        };
+
+        Ok(Self {
+            qname,
+            qtype,
+            qclass,
+            rcode: rcode_mnemonic(message.response_code()),
+            flags: DnsFlags::from_message(&message),
+            answers: message.answers().iter().map(DnsRecord::from).collect(),
+            authority: message.name_servers().iter().map(DnsRecord::from).collect(),
+            additional: message.additionals().iter().map(DnsRecord::from).collect(),
+        })
+    }
+}
+
+/// Returns the IANA mnemonic for a response code, the same as dig prints in its status line
+fn rcode_mnemonic(rcode: ResponseCode) -> String {
+    let code = u16::from(rcode);
+    let mnemonic = match code {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        16 => "BADVERS",
+        _ => return format!("RCODE{code}"),
+    };
+    mnemonic.to_string()
+}
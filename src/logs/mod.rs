@@ -1,43 +1,66 @@
+mod dns_message;
 mod query_log;
 mod query_logs;
 mod usage_stats;
 
+pub use dns_message::*;
 pub use query_log::*;
 pub use query_logs::*;
 pub use usage_stats::*;
 
-use crate::tasks::dnstap::read_dnstap_logs;
+use tokio::sync::mpsc::Receiver;
 
-#[derive(Debug, Clone)]
+use crate::dnstap::Message;
+
+#[derive(Debug)]
 pub struct LogsConsumer {
     logs_store: QueryLogs,
     usage_stats: UsageStats,
+    receiver: Receiver<Message>,
 }
 
 impl LogsConsumer {
-    pub fn new(logs_store: QueryLogs, usage_stats: UsageStats) -> Self {
+    pub fn new(
+        logs_store: QueryLogs,
+        usage_stats: UsageStats,
+        receiver: Receiver<Message>,
+    ) -> Self {
         Self {
             logs_store,
             usage_stats,
+            receiver,
         }
     }
 
-    pub async fn ingest_logs_from_file(&self) {
+    pub async fn ingest_logs(&mut self) {
         tracing::trace!("LogsStore remove_expired_logs");
         self.logs_store.remove_expired_logs();
         tracing::trace!("LogsStore remove_expired_logs. DONE");
 
-        tracing::trace!("LogsStore read_dnstap_logs");
-        let content = read_dnstap_logs().await;
-        let content_len = content.len();
-        tracing::trace!("LogsStore read_dnstap_logs. DONE, content_len={content_len}");
+        tracing::trace!("LogsStore receive_query_logs");
+        let mut query_logs = Vec::new();
+        let mut invalid_logs = 0;
+        while let Ok(message) = self.receiver.try_recv() {
+            match QueryLog::try_from(&message) {
+                Ok(query_log) => query_logs.push(query_log),
+                Err(err) => {
+                    tracing::debug!("LogsStore fail to extract query log: {err:#}");
+                    invalid_logs += 1;
+                }
+            }
+        }
+        let query_logs_len = query_logs.len();
+        tracing::trace!("LogsStore receive_query_logs. DONE, query_logs_len={query_logs_len}");
+
+        if invalid_logs > 0 {
+            tracing::warn!("LogsStore skipped {invalid_logs} query logs that could not be parsed");
+            self.usage_stats.add_invalid_logs(invalid_logs);
+        }
 
-        tracing::trace!("LogsStore extract_query_logs");
-        let logs_hash_map = extract_query_logs(&content);
+        tracing::trace!("LogsStore group_query_logs");
+        let logs_hash_map = group_query_logs(query_logs);
         let logs_hash_map_len = logs_hash_map.len();
-        tracing::trace!(
-            "LogsStore extract_query_logs. DONE, logs_hash_map_len={logs_hash_map_len}"
-        );
+        tracing::trace!("LogsStore group_query_logs. DONE, logs_hash_map_len={logs_hash_map_len}");
 
         tracing::trace!("LogsStore logs_hash_map");
         self.logs_store.merge_logs(&logs_hash_map);
@@ -1,93 +1,86 @@
 use std::collections::HashMap;
 
-use chrono::{DateTime, NaiveDateTime, Utc};
-
-#[allow(dead_code)]
-#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
-pub struct RawMessage {
-    #[serde(rename = "type")]
-    _type: String,
-
-    query_time: String,
-    query_address: String,
-    response_address: String,
-    response_message: String,
-}
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
 
-#[allow(dead_code)]
-#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
-pub struct RawLog {
-    #[serde(rename = "type")]
-    _type: String,
+use super::DnsResponse;
+use crate::dnstap::{parse_address, Message, SocketProtocol};
 
-    identity: String,
-    version: String,
-    message: RawMessage,
-}
+fn parse_query_time(sec: Option<u64>, nsec: Option<u32>) -> Result<DateTime<Utc>, anyhow::Error> {
+    let Some(sec) = sec else {
+        bail!("missing timestamp");
+    };
+
+    let nsec = nsec.unwrap_or_default();
+    if nsec >= 1_000_000_000 {
+        bail!("invalid timestamp nanoseconds: {nsec}");
+    }
 
-fn parse_query_time(query_time: &str) -> DateTime<Utc> {
-    let query_time = query_time.replace("!!timestamp", "").trim().to_string();
-    let (query_time, _) =
-        NaiveDateTime::parse_and_remainder(&query_time, "%Y-%m-%d %H:%M:%S").unwrap_or_default();
-    query_time.and_utc()
+    let Ok(sec) = i64::try_from(sec) else {
+        bail!("timestamp out of range: {sec}");
+    };
+
+    DateTime::from_timestamp(sec, nsec).with_context(|| format!("timestamp out of range: {sec}"))
 }
 
 #[derive(serde::Serialize, Debug, Clone, PartialEq)]
 pub struct QueryLog {
     pub ip: String,
+    pub port: u16,
+    pub protocol: String,
     pub query_time: chrono::DateTime<Utc>,
-    pub question: String,
-    pub answers: Vec<String>,
+    pub latency_ms: Option<f64>,
+    #[serde(flatten)]
+    pub response: DnsResponse,
 }
 
-impl From<&RawLog> for QueryLog {
-    fn from(raw_log: &RawLog) -> Self {
-        let ip = raw_log.message.query_address.to_string();
-        let query_time = parse_query_time(&raw_log.message.query_time);
-        let response_message = &raw_log.message.response_message;
-
-        let question: String = response_message
-            .split('\n')
-            .skip_while(|s| *s != ";; QUESTION SECTION:")
-            .skip(1)
-            .take(1)
-            .next()
-            .map(|s| s.to_string())
-            .unwrap_or_default()
-            .replace('\t', "");
-
-        let answers: Vec<String> = response_message
-            .split('\n')
-            .skip_while(|s| *s != ";; ANSWER SECTION:")
-            .skip(1)
-            .take_while(|s| !s.is_empty())
-            .map(|s| s.to_string())
-            .collect();
-
-        QueryLog {
+impl TryFrom<&Message> for QueryLog {
+    type Error = anyhow::Error;
+
+    fn try_from(message: &Message) -> Result<Self, Self::Error> {
+        let ip = message
+            .query_address
+            .as_deref()
+            .and_then(parse_address)
+            .context("missing query_address")?
+            .to_string();
+        let port = message.query_port.unwrap_or_default() as u16;
+
+        let protocol = message
+            .socket_protocol
+            .and_then(|p| SocketProtocol::try_from(p).ok())
+            .map(|p| p.as_str().to_string())
+            .unwrap_or_default();
+
+        let query_time = parse_query_time(message.query_time_sec, message.query_time_nsec)
+            .context("invalid query_time")?;
+
+        let latency_ms = parse_query_time(message.response_time_sec, message.response_time_nsec)
+            .ok()
+            .and_then(|response_time| (response_time - query_time).num_microseconds())
+            .map(|us| us as f64 / 1000.0);
+
+        let response_message = message
+            .response_message
+            .as_deref()
+            .context("missing response_message")?;
+        let response = DnsResponse::parse(response_message)?;
+
+        Ok(QueryLog {
             ip,
+            port,
+            protocol,
             query_time,
-            question,
-            answers,
-        }
+            latency_ms,
+            response,
+        })
     }
 }
 
-pub fn extract_query_logs(content: &str) -> HashMap<String, Vec<QueryLog>> {
+pub fn group_query_logs(query_logs: Vec<QueryLog>) -> HashMap<String, Vec<QueryLog>> {
     let mut logs_store: HashMap<String, Vec<QueryLog>> = HashMap::new();
 
-    let content_parts = content
-        .split("\n---\n")
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty());
-
-    for part in content_parts {
-        let Ok(raw_log) = serde_yaml::from_str::<RawLog>(part) else {
-            tracing::info!("extract_query_logs fail to extract part: {part}");
-            continue;
-        };
-
-        let query_log = QueryLog::from(&raw_log);
+    for query_log in query_logs {
         match logs_store.get_mut(&query_log.ip) {
             Some(queries) => {
                 queries.push(query_log);
@@ -103,66 +96,145 @@ pub fn extract_query_logs(content: &str) -> HashMap<String, Vec<QueryLog>> {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
-
-    use chrono::TimeZone;
-
-    use super::{extract_query_logs, parse_query_time, QueryLog};
+    use std::{collections::HashMap, net::Ipv4Addr, str::FromStr};
+
+    use chrono::{TimeZone, Timelike};
+    use hickory_proto::{
+        op::{Message as DnsMessage, MessageType, Query},
+        rr::{
+            rdata::{A, CNAME, SOA},
+            Name, RData, Record, RecordType,
+        },
+    };
+
+    use super::{group_query_logs, parse_query_time, QueryLog};
+    use crate::dnstap::{self, Message, SocketFamily, SocketProtocol};
+    use crate::logs::{DnsFlags, DnsRecord, DnsResponse};
+
+    fn response_message() -> Vec<u8> {
+        let zedo = Name::from_str("zedo.com.").unwrap();
+        let null = Name::from_str("null.null-zone.null.").unwrap();
+
+        let mut message = DnsMessage::new();
+        message.set_id(50897);
+        message.set_message_type(MessageType::Response);
+        message.set_recursion_desired(true);
+        message.set_recursion_available(true);
+        message.add_query(Query::query(zedo.clone(), RecordType::A));
+        message.add_answer(Record::from_rdata(
+            zedo,
+            5,
+            RData::CNAME(CNAME(null.clone())),
+        ));
+        message.add_answer(Record::from_rdata(
+            null,
+            86400,
+            RData::A(A(Ipv4Addr::new(0, 0, 0, 0))),
+        ));
+        message.add_additional(Record::from_rdata(
+            Name::from_str("blacklist.").unwrap(),
+            1,
+            RData::SOA(SOA::new(
+                Name::from_str("localhost.").unwrap(),
+                Name::from_str("named-mgr.example.com.blacklist.").unwrap(),
+                1,
+                3600,
+                900,
+                2592000,
+                7200,
+            )),
+        ));
+        message.to_vec().unwrap()
+    }
 
     #[test]
     fn test_parse_query_time() {
-        let query_time = "!!timestamp 2022-02-26 09:25:07.665010146";
-        let output = parse_query_time(query_time);
-        let expected = chrono::Utc.with_ymd_and_hms(2022, 2, 26, 9, 25, 7).unwrap();
+        let output = parse_query_time(Some(1645867507), Some(665010146)).unwrap();
+        let expected = chrono::Utc
+            .with_ymd_and_hms(2022, 2, 26, 9, 25, 7)
+            .unwrap()
+            .with_nanosecond(665010146)
+            .unwrap();
         assert_eq!(output, expected)
     }
 
+    #[test]
+    fn test_parse_query_time_invalid() {
+        assert!(parse_query_time(None, Some(665010146)).is_err());
+        assert!(parse_query_time(Some(1645867507), Some(1_500_000_000)).is_err());
+        assert!(parse_query_time(Some(u64::MAX), None).is_err());
+    }
+
     #[test]
     fn test_extract_queries() {
-        let input = r#"
-type: MESSAGE
-identity: "dns"
-version: "dnsdist 1.6.1"
-message:
-  type: CLIENT_RESPONSE
-  query_time: !!timestamp 2022-02-26 09:25:07.665010146
-  response_time: !!timestamp 2022-02-26 09:25:10.493649953
-  socket_family: INET
-  socket_protocol: UDP
-  query_address: 127.0.0.1
-  response_address: 127.0.0.1
-  query_port: 45523
-  response_port: 1253
-  response_message: |
-    ;; opcode: QUERY, status: NOERROR, id: 50897
-    ;; flags: qr rd ra; QUERY: 1, ANSWER: 2, AUTHORITY: 0, ADDITIONAL: 1
-    
-    ;; QUESTION SECTION:
-    ;zedo.com.	IN	 A
-    
-    ;; ANSWER SECTION:
-    zedo.com.	5	IN	CNAME	null.null-zone.null.
-    null.null-zone.null.	86400	IN	A	0.0.0.0
-    
-    ;; ADDITIONAL SECTION:
-    blacklist.	1	IN	SOA	LOCALHOST. named-mgr.example.com.blacklist. 1 3600 900 2592000 7200
-"#
-        .trim();
+        let message = Message {
+            r#type: dnstap::MessageType::ClientResponse as i32,
+            socket_family: Some(SocketFamily::Inet as i32),
+            socket_protocol: Some(SocketProtocol::Udp as i32),
+            query_address: Some(vec![127, 0, 0, 1]),
+            response_address: Some(vec![127, 0, 0, 1]),
+            query_port: Some(45523),
+            response_port: Some(1253),
+            query_time_sec: Some(1645867507),
+            query_time_nsec: Some(665010146),
+            query_message: None,
+            response_time_sec: Some(1645867507),
+            response_time_nsec: Some(693649953),
+            response_message: Some(response_message()),
+        };
 
         let expected = HashMap::from([(
             "127.0.0.1".to_string(),
             vec![QueryLog {
                 ip: "127.0.0.1".to_string(),
-                query_time: chrono::Utc.with_ymd_and_hms(2022, 2, 26, 9, 25, 7).unwrap(),
-                question: ";zedo.com.IN A".to_string(),
-                answers: vec![
-                    "zedo.com.\t5\tIN\tCNAME\tnull.null-zone.null.".to_string(),
-                    "null.null-zone.null.\t86400\tIN\tA\t0.0.0.0".to_string(),
-                ],
+                port: 45523,
+                protocol: "UDP".to_string(),
+                query_time: chrono::Utc
+                    .with_ymd_and_hms(2022, 2, 26, 9, 25, 7)
+                    .unwrap()
+                    .with_nanosecond(665010146)
+                    .unwrap(),
+                latency_ms: Some(28.639),
+                response: DnsResponse {
+                    qname: "zedo.com.".to_string(),
+                    qtype: "A".to_string(),
+                    qclass: "IN".to_string(),
+                    rcode: "NOERROR".to_string(),
+                    flags: DnsFlags {
+                        qr: true,
+                        rd: true,
+                        ra: true,
+                        ..Default::default()
+                    },
+                    answers: vec![
+                        DnsRecord {
+                            name: "zedo.com.".to_string(),
+                            ttl: 5,
+                            rtype: "CNAME".to_string(),
+                            rdata: "null.null-zone.null.".to_string(),
+                        },
+                        DnsRecord {
+                            name: "null.null-zone.null.".to_string(),
+                            ttl: 86400,
+                            rtype: "A".to_string(),
+                            rdata: "0.0.0.0".to_string(),
+                        },
+                    ],
+                    authority: vec![],
+                    additional: vec![DnsRecord {
+                        name: "blacklist.".to_string(),
+                        ttl: 1,
+                        rtype: "SOA".to_string(),
+                        rdata:
+                            "localhost. named-mgr.example.com.blacklist. 1 3600 900 2592000 7200"
+                                .to_string(),
+                    }],
+                },
             }],
         )]);
 
-        let output = extract_query_logs(input);
+        let query_log = QueryLog::try_from(&message).unwrap();
+        let output = group_query_logs(vec![query_log]);
         assert_eq!(output, expected);
     }
 }
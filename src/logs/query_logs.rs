@@ -30,18 +30,23 @@ impl QueryLogs {
             match logs_store_guard.get_mut(ip) {
                 Some(existing_logs) => {
                     existing_logs.extend(logs.clone());
+                    existing_logs.sort_by_key(|q| q.query_time);
                 }
                 None => {
-                    logs_store_guard.insert(ip.to_string(), logs.clone());
+                    let mut logs = logs.clone();
+                    logs.sort_by_key(|q| q.query_time);
+                    logs_store_guard.insert(ip.to_string(), logs);
                 }
             }
         }
     }
 
     pub fn get_logs_for_ip(&self, ip: &str) -> Vec<QueryLog> {
-        match self.logs_store.lock().unwrap().get(ip).cloned() {
-            Some(logs) => logs,
-            None => Vec::new(),
-        }
+        self.logs_store
+            .lock()
+            .unwrap()
+            .get(ip)
+            .cloned()
+            .unwrap_or_default()
     }
 }
@@ -1,6 +1,9 @@
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
 
 use chrono::{DateTime, Duration, Utc};
@@ -10,6 +13,7 @@ use super::QueryLog;
 #[derive(Debug, Clone, Default)]
 pub struct UsageStats {
     active_ips: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+    invalid_logs: Arc<AtomicUsize>,
 }
 
 impl UsageStats {
@@ -17,7 +21,7 @@ impl UsageStats {
         let mut last_query_times = self.active_ips.lock().unwrap().clone();
 
         for (ip, queries) in logs_hash_map.iter() {
-            let Some(last_qt) = queries.last().map(|q| q.query_time) else {
+            let Some(last_qt) = queries.iter().map(|q| q.query_time).max() else {
                 continue;
             };
 
@@ -41,4 +45,12 @@ impl UsageStats {
     pub fn get_active_ips(&self) -> usize {
         self.active_ips.lock().unwrap().len()
     }
+
+    pub fn add_invalid_logs(&self, count: usize) {
+        self.invalid_logs.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn get_invalid_logs(&self) -> usize {
+        self.invalid_logs.load(Ordering::Relaxed)
+    }
 }
@@ -1,36 +1,325 @@
+mod acme;
+mod certs;
+mod dnsdist;
+mod dnstap;
 mod handler;
 mod logs;
+mod metrics;
+mod settings;
 mod tasks;
+mod tls;
 
-use std::{net::SocketAddr, path::PathBuf, time::Duration};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
-use axum::{extract::connect_info::IntoMakeServiceWithConnectInfo, routing::get, Router};
+use anyhow::Context;
+use axum::{
+    extract::connect_info::IntoMakeServiceWithConnectInfo,
+    routing::{get, post},
+    Router,
+};
 use axum_server::{tls_rustls::RustlsConfig, Handle};
-use clap::Parser;
-use handler::AppState;
+use base64::Engine;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use handler::{AdminApi, AppState};
 use logs::{LogsConsumer, QueryLogs, UsageStats};
 use tokio::signal::unix::{signal, SignalKind};
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tower_http::services::ServeDir;
 use tower_http::timeout::{RequestBodyTimeoutLayer, ResponseBodyTimeoutLayer, TimeoutLayer};
 
-use crate::handler::{get_logs, get_logs_api};
-use crate::tasks::certbot::CertbotTask;
-use crate::tasks::dnsdist::{run_dnsdist_reload_cert, spawn_dnsdist};
-use crate::tasks::dnstap::spawn_dnstap;
+use crate::acme::{
+    ChallengeSolver, DnsProvider, ExecProvider, ExternalAccountBinding, Http01Challenges,
+    Rfc2136Provider,
+};
+use crate::certs::{
+    certificate_specs, CertMode, CertificatePaths, KeyPolicy, KeyType, LocalCa, CERTS_DIR,
+};
+use crate::dnsdist::{
+    allowed_networks, generate_console_key, load_console_key, Acl, Backend, Console, DnsdistConfig,
+    DnsdistConsole, Dnstap, DynBlockRule, DynBlockTrigger, DynBlocks, LimitAction, Listener,
+    ListenerAcl, ListenerAcls, ListenerKind, Netmask, Protocol, RateLimit, Route, Rule, Selector,
+    ServerPolicy,
+};
+use crate::handler::{
+    get_acme_challenge, get_blocks_api, get_logs, get_logs_api, get_metrics, get_processes_api,
+    get_routes_api, get_tls_api, post_reload_api,
+};
+use crate::settings::{effective_settings, parse_with_config_file, ConfigFormat};
+use crate::tasks::acme::{retry_delay, AcmeSettings, AcmeTask};
+use crate::tasks::dnsdist::{
+    install_config, write_checked_config, DnsdistProcess, DnsdistReloader, ReloadOutcome,
+    ReloadTrigger,
+};
+use crate::tasks::dnstap::{run_dnstap_listener, DNSTAP_CHANNEL_CAPACITY, DNSTAP_SOCKET};
+use crate::tasks::file_watcher::FileWatcher;
+use crate::tasks::supervisor::{RestartPolicy, RestartSettings, Supervisor, SupervisorStatus};
+use crate::tls::TlsStatus;
 
-#[derive(Parser, Debug)]
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum AcmeChallenge {
+    #[value(name = "http-01")]
+    Http01,
+    #[value(name = "dns-01")]
+    Dns01,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum AcmeCa {
+    Letsencrypt,
+    LetsencryptStaging,
+    Zerossl,
+    Google,
+    GoogleStaging,
+}
+
+impl AcmeCa {
+    fn directory_url(&self) -> &'static str {
+        match self {
+            AcmeCa::Letsencrypt => "https://acme-v02.api.letsencrypt.org/directory",
+            AcmeCa::LetsencryptStaging => "https://acme-staging-v02.api.letsencrypt.org/directory",
+            AcmeCa::Zerossl => "https://acme.zerossl.com/v2/DV90",
+            AcmeCa::Google => "https://dv.acme-v02.api.pki.goog/directory",
+            AcmeCa::GoogleStaging => "https://dv.acme-v02.test-api.pki.goog/directory",
+        }
+    }
+
+    fn requires_eab(&self) -> bool {
+        matches!(
+            self,
+            AcmeCa::Zerossl | AcmeCa::Google | AcmeCa::GoogleStaging
+        )
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum TlsSource {
+    Acme,
+    Files,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Dns01Provider {
+    Rfc2136,
+    Exec,
+}
+
+#[derive(Parser, Debug, Clone)]
 #[command(name = "DnsDist ACME")]
 #[command(version)]
 #[command(about)]
 struct Args {
+    /// Reads the settings from a YAML or TOML file. The environment and the command line
+    /// override them
+    #[arg(long, env, value_name = "CONFIG_FILE")]
+    config_file: Option<PathBuf>,
+
+    /// Prints the settings in effect and exits, as yaml or with `--print-config=toml` as toml
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "yaml"
+    )]
+    print_config: Option<ConfigFormat>,
+
     /// Sets a custom l istener port
     #[arg(long, env, value_name = "PORT", default_value = "53")]
     port: u16,
 
-    /// Sets a backend port to forward the requests to
-    #[arg(long, env, value_name = "BACKEND", default_value = "8.8.8.8:53")]
-    backend: SocketAddr,
+    /// Sets the backends to forward the requests to, comma separated. Each one is an address
+    /// followed by optional `;key=value` options: name, weight, order, pool, health-check,
+    /// check-interval, check-name, transport, doh-path, subject-name, validate-certificates and
+    /// ca-store
+    #[arg(
+        long,
+        env,
+        value_name = "BACKEND",
+        value_delimiter = ',',
+        default_value = "8.8.8.8:53"
+    )]
+    backend: Vec<Backend>,
+
+    /// Sets how a backend is picked for each query
+    #[arg(
+        long,
+        env,
+        value_name = "BACKEND_POLICY",
+        default_value = "least-outstanding"
+    )]
+    backend_policy: ServerPolicy,
+
+    /// Sends the queries for names under some suffixes to a backend pool, comma separated
+    /// `SUFFIX[;SUFFIX...]=POOL` entries, e.g. `corp.example;lan=internal`
+    #[arg(long, env, value_name = "ROUTE", value_delimiter = ',')]
+    route: Vec<Route>,
+
+    /// Sets the networks allowed to query on the DNS53 port, comma separated. Every network is allowed when empty
+    #[arg(long, env, value_name = "ACL_DNS_ALLOW", value_delimiter = ',')]
+    acl_dns_allow: Vec<Netmask>,
+
+    /// Sets the networks denied on the DNS53 port, comma separated. They win over the allowed networks
+    #[arg(long, env, value_name = "ACL_DNS_DENY", value_delimiter = ',')]
+    acl_dns_deny: Vec<Netmask>,
+
+    /// Sets the networks allowed to query over DoH, comma separated. Every network is allowed when empty
+    #[arg(long, env, value_name = "ACL_DOH_ALLOW", value_delimiter = ',')]
+    acl_doh_allow: Vec<Netmask>,
+
+    /// Sets the networks denied over DoH, comma separated. They win over the allowed networks
+    #[arg(long, env, value_name = "ACL_DOH_DENY", value_delimiter = ',')]
+    acl_doh_deny: Vec<Netmask>,
+
+    /// Sets the networks allowed to query over DoT, comma separated. Every network is allowed when empty
+    #[arg(long, env, value_name = "ACL_DOT_ALLOW", value_delimiter = ',')]
+    acl_dot_allow: Vec<Netmask>,
+
+    /// Sets the networks denied over DoT, comma separated. They win over the allowed networks
+    #[arg(long, env, value_name = "ACL_DOT_DENY", value_delimiter = ',')]
+    acl_dot_deny: Vec<Netmask>,
+
+    /// Sets a file of `<dns|doh|dot> <allow|deny> <netmask>` lines, added to the acls above.
+    /// Changes to the file are applied without restarting dnsdist
+    #[arg(long, env, value_name = "ACL_FILE")]
+    acl_file: Option<PathBuf>,
+
+    /// Sets how often the acl file is checked for changes
+    #[arg(
+        long,
+        env,
+        value_name = "ACL_WATCH_INTERVAL_SECONDS",
+        default_value = "10"
+    )]
+    acl_watch_interval_seconds: u64,
+
+    /// Sets the address of the dnsdist console, it must be a loopback address
+    #[arg(
+        long,
+        env,
+        value_name = "CONSOLE_ADDRESS",
+        default_value = "127.0.0.1:5199"
+    )]
+    console_address: SocketAddr,
+
+    /// Sets a file with the base64 dnsdist console key. A random key is generated at every start when not set
+    #[arg(long, env, value_name = "CONSOLE_KEY_FILE")]
+    console_key_file: Option<PathBuf>,
+
+    /// Sets a file with the bearer token of the admin api reloads, which are disabled when not set
+    #[arg(long, env, value_name = "ADMIN_TOKEN_FILE")]
+    admin_token_file: Option<PathBuf>,
+
+    /// Sets the address of the admin api, which must be a loopback address
+    #[arg(
+        long,
+        env,
+        value_name = "ADMIN_ADDRESS",
+        default_value = "127.0.0.1:8081"
+    )]
+    admin_address: SocketAddr,
+
+    /// Sets the per network query limits, comma separated `QPS[;key=value...]` entries with
+    /// v4-prefix, v6-prefix and action (drop, truncate or refused) options. 0 disables them
+    #[arg(
+        long,
+        env,
+        value_name = "RATE_LIMIT",
+        value_delimiter = ',',
+        default_value = "10;v4-prefix=32;v6-prefix=48;action=drop"
+    )]
+    rate_limit: Vec<RateLimit>,
+
+    /// Blocks the networks that send more queries per second than this
+    #[arg(long, env, value_name = "DYN_BLOCK_QPS")]
+    dyn_block_qps: Option<u32>,
+
+    /// Blocks the networks that get more NXDOMAIN responses per second than this
+    #[arg(long, env, value_name = "DYN_BLOCK_NXDOMAIN_RATE")]
+    dyn_block_nxdomain_rate: Option<u32>,
+
+    /// Blocks the networks that get more SERVFAIL responses per second than this
+    #[arg(long, env, value_name = "DYN_BLOCK_SERVFAIL_RATE")]
+    dyn_block_servfail_rate: Option<u32>,
+
+    /// Blocks the networks that get more response bytes per second than this
+    #[arg(long, env, value_name = "DYN_BLOCK_BYTES_RATE")]
+    dyn_block_bytes_rate: Option<u32>,
+
+    /// Sets the window the dynamic block rates are measured over
+    #[arg(
+        long,
+        env,
+        value_name = "DYN_BLOCK_WINDOW_SECONDS",
+        default_value = "10",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    dyn_block_window_seconds: u32,
+
+    /// Sets how long a network stays blocked
+    #[arg(
+        long,
+        env,
+        value_name = "DYN_BLOCK_DURATION_SECONDS",
+        default_value = "60",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    dyn_block_duration_seconds: u32,
+
+    /// Sets what is done with the queries of a blocked network
+    #[arg(long, env, value_name = "DYN_BLOCK_ACTION", default_value = "drop")]
+    dyn_block_action: LimitAction,
+
+    /// Sets the ipv4 prefix length that clients are grouped and blocked by
+    #[arg(long, env, value_name = "DYN_BLOCK_V4_PREFIX", default_value = "32", value_parser = clap::value_parser!(u8).range(0..=32))]
+    dyn_block_v4_prefix: u8,
+
+    /// Sets the ipv6 prefix length that clients are grouped and blocked by
+    #[arg(long, env, value_name = "DYN_BLOCK_V6_PREFIX", default_value = "64", value_parser = clap::value_parser!(u8).range(0..=128))]
+    dyn_block_v6_prefix: u8,
+
+    /// Sets when dnsdist is restarted after it exits. Every task is stopped once it is not restarted
+    #[arg(
+        long,
+        env,
+        value_name = "DNSDIST_RESTART_POLICY",
+        default_value = "always"
+    )]
+    dnsdist_restart_policy: RestartPolicy,
+
+    /// Sets when the dnstap listener, which collects the query logs, is restarted after it exits
+    #[arg(
+        long,
+        env,
+        value_name = "DNSTAP_RESTART_POLICY",
+        default_value = "always"
+    )]
+    dnstap_restart_policy: RestartPolicy,
+
+    /// Sets the delay before the first restart, it doubles after every following restart
+    #[arg(long, env, value_name = "RESTART_BACKOFF_SECONDS", default_value = "1")]
+    restart_backoff_seconds: u64,
+
+    /// Sets the longest delay between restarts
+    #[arg(
+        long,
+        env,
+        value_name = "RESTART_MAX_BACKOFF_SECONDS",
+        default_value = "60"
+    )]
+    restart_max_backoff_seconds: u64,
+
+    /// Sets how many restarts are allowed within RESTART_WINDOW_SECONDS before giving up
+    #[arg(long, env, value_name = "RESTART_MAX", default_value = "5")]
+    restart_max: usize,
+
+    /// Sets the window that restarts are counted over
+    #[arg(
+        long,
+        env,
+        value_name = "RESTART_WINDOW_SECONDS",
+        default_value = "300"
+    )]
+    restart_window_seconds: u64,
 
     /// If enabled, obtains a tls cert from letsencrypt and enable doh and dot protocols
     #[arg(long, env, value_name = "TLS_ENABLED")]
@@ -40,20 +329,567 @@ struct Args {
     #[arg(long, env, value_name = "TLS_EMAIL")]
     tls_email: Option<String>,
 
-    /// Sets the domain used for letsencrypt
-    #[arg(long, env, value_name = "TLS_DOMAIN")]
-    tls_domain: Option<String>,
+    /// Sets the domains used for letsencrypt, comma separated. The first one is the default certificate
+    #[arg(long, env, value_name = "TLS_DOMAIN", value_delimiter = ',')]
+    tls_domain: Vec<String>,
+
+    /// Sets whether the domains share one SAN certificate or get a certificate each
+    #[arg(long, env, value_name = "TLS_CERT_MODE", default_value = "san")]
+    tls_cert_mode: CertMode,
+
+    /// Sets the algorithm of the tls cert private keys
+    #[arg(long, env, value_name = "TLS_KEY_TYPE", default_value = "ecdsa-p256")]
+    tls_key_type: KeyType,
+
+    /// Sets whether renewed tls certs keep their private key or get a new one
+    #[arg(long, env, value_name = "TLS_KEY_POLICY", default_value = "rotate")]
+    tls_key_policy: KeyPolicy,
+
+    /// Sets where the tls certs come from, either obtained with ACME or read from files
+    #[arg(long, env, value_name = "TLS_SOURCE", default_value = "acme")]
+    tls_source: TlsSource,
+
+    /// Sets the cert files to serve with the `files` source, comma separated. The first one is the default certificate
+    #[arg(long, env, value_name = "TLS_CERT_FILE", value_delimiter = ',')]
+    tls_cert_file: Vec<PathBuf>,
+
+    /// Sets the key files of the certs, in the same order
+    #[arg(long, env, value_name = "TLS_KEY_FILE", value_delimiter = ',')]
+    tls_key_file: Vec<PathBuf>,
+
+    /// Sets how often the cert files are checked for changes with the `files` source
+    #[arg(
+        long,
+        env,
+        value_name = "TLS_WATCH_INTERVAL_SECONDS",
+        default_value = "10"
+    )]
+    tls_watch_interval_seconds: u64,
+
+    /// Sets the CA cert that signs the fallback certs served until ACME succeeds. They are self-signed when not set
+    #[arg(
+        long,
+        env,
+        value_name = "TLS_FALLBACK_CA_CERT",
+        requires = "tls_fallback_ca_key"
+    )]
+    tls_fallback_ca_cert: Option<PathBuf>,
+
+    /// Sets the key of the fallback CA cert
+    #[arg(
+        long,
+        env,
+        value_name = "TLS_FALLBACK_CA_KEY",
+        requires = "tls_fallback_ca_cert"
+    )]
+    tls_fallback_ca_key: Option<PathBuf>,
+
+    /// Sets the ACME CA used to obtain the tls cert
+    #[arg(long, env, value_name = "ACME_CA", default_value = "letsencrypt")]
+    acme_ca: AcmeCa,
+
+    /// Sets the ACME directory url used to obtain the tls cert, e.g. of a step-ca server. Overrides ACME_CA
+    #[arg(long, env, value_name = "ACME_DIRECTORY_URL")]
+    acme_directory_url: Option<String>,
+
+    /// Sets the key id for the external account binding that some CAs require
+    #[arg(long, env, value_name = "ACME_EAB_KID", requires = "acme_eab_hmac_key")]
+    acme_eab_kid: Option<String>,
+
+    /// Sets the base64url hmac key for the external account binding
+    #[arg(long, env, value_name = "ACME_EAB_HMAC_KEY", requires = "acme_eab_kid")]
+    acme_eab_hmac_key: Option<String>,
+
+    /// Sets the issuer of the topmost cert of the preferred chain, e.g. "ISRG Root X1"
+    #[arg(long, env, value_name = "ACME_PREFERRED_CHAIN")]
+    acme_preferred_chain: Option<String>,
+
+    /// Sets the port that answers ACME http-01 challenges
+    #[arg(long, env, value_name = "ACME_HTTP_PORT", default_value = "80")]
+    acme_http_port: u16,
+
+    /// Sets how many days before expiry the tls certs are renewed, 1 to 89 as the certs are
+    /// valid for 90 days
+    #[arg(
+        long,
+        env,
+        value_name = "ACME_RENEW_BEFORE_DAYS",
+        default_value = "30",
+        value_parser = clap::value_parser!(i64).range(1..90)
+    )]
+    acme_renew_before_days: i64,
+
+    /// Sets the ACME challenge type. dns-01 does not need port 80, and allows wildcard domains
+    #[arg(long, env, value_name = "ACME_CHALLENGE", default_value = "http-01")]
+    acme_challenge: AcmeChallenge,
+
+    /// Sets the backend that publishes the dns-01 TXT records
+    #[arg(long, env, value_name = "DNS01_PROVIDER")]
+    dns01_provider: Option<Dns01Provider>,
+
+    /// Sets how long to wait for the dns-01 TXT records to propagate
+    #[arg(
+        long,
+        env,
+        value_name = "DNS01_PROPAGATION_SECONDS",
+        default_value = "30"
+    )]
+    dns01_propagation_seconds: u64,
+
+    /// Sets the authoritative server that accepts rfc2136 dynamic updates
+    #[arg(long, env, value_name = "DNS01_RFC2136_SERVER")]
+    dns01_rfc2136_server: Option<SocketAddr>,
+
+    /// Sets the zone to update, looked up from the server's SOA when not set
+    #[arg(long, env, value_name = "DNS01_RFC2136_ZONE")]
+    dns01_rfc2136_zone: Option<String>,
+
+    /// Sets the TSIG key name used to sign the rfc2136 updates
+    #[arg(long, env, value_name = "DNS01_RFC2136_TSIG_NAME")]
+    dns01_rfc2136_tsig_name: Option<String>,
+
+    /// Sets the base64 TSIG secret used to sign the rfc2136 updates
+    #[arg(long, env, value_name = "DNS01_RFC2136_TSIG_SECRET")]
+    dns01_rfc2136_tsig_secret: Option<String>,
+
+    /// Sets the TSIG algorithm used to sign the rfc2136 updates
+    #[arg(
+        long,
+        env,
+        value_name = "DNS01_RFC2136_TSIG_ALGORITHM",
+        default_value = "hmac-sha256"
+    )]
+    dns01_rfc2136_tsig_algorithm: String,
+
+    /// Sets the hook called as `<command> present|cleanup <fqdn> <value>` for the exec provider
+    #[arg(long, env, value_name = "DNS01_EXEC_COMMAND")]
+    dns01_exec_command: Option<PathBuf>,
+
+    /// Sets how long the exec provider hook may run before it is killed
+    #[arg(
+        long,
+        env,
+        value_name = "DNS01_EXEC_TIMEOUT_SECONDS",
+        default_value = "60",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    dns01_exec_timeout_seconds: u64,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
 }
 
-fn make_service(
-    logs_store: QueryLogs,
-    usage_stats: UsageStats,
-) -> IntoMakeServiceWithConnectInfo<Router, SocketAddr> {
-    let app_state = AppState::new(logs_store, usage_stats);
+#[derive(Subcommand, Clone, Debug)]
+enum Commands {
+    /// Renders the dnsdist config from the settings and checks it with dnsdist, without starting anything
+    CheckConfig,
+}
+
+/// The args that are not settings, so are not read from the config file or printed
+const NOT_SETTINGS: &[&str] = &["config_file", "print_config"];
+
+/// The settings that are redacted when printed or logged
+const SECRET_SETTINGS: &[&str] = &["dns01_rfc2136_tsig_secret", "acme_eab_hmac_key"];
+
+/// Parses the args from the command line, the environment and the config file
+fn parse_args() -> Result<(Args, ArgMatches), anyhow::Error> {
+    let argv = std::env::args_os().collect();
+    let matches = parse_with_config_file(Args::command(), argv, "config_file", NOT_SETTINGS)?;
+    let args = Args::from_arg_matches(&matches)?;
+    Ok((args, matches))
+}
+
+fn make_challenge_solver(
+    args: &Args,
+    challenges: Http01Challenges,
+) -> Result<ChallengeSolver, anyhow::Error> {
+    if args.acme_challenge == AcmeChallenge::Http01 {
+        return Ok(ChallengeSolver::Http01(challenges));
+    }
+
+    let provider: Arc<dyn DnsProvider> = match args.dns01_provider {
+        Some(Dns01Provider::Rfc2136) => {
+            let server = args
+                .dns01_rfc2136_server
+                .context("dns01_rfc2136_server is not set")?;
+            let mut provider = Rfc2136Provider::new(server, args.dns01_rfc2136_zone.as_deref())?;
+
+            if let Some(key_name) = &args.dns01_rfc2136_tsig_name {
+                let secret = args
+                    .dns01_rfc2136_tsig_secret
+                    .as_deref()
+                    .context("dns01_rfc2136_tsig_secret is not set")?;
+                let secret = base64::engine::general_purpose::STANDARD.decode(secret)?;
+                provider =
+                    provider.with_tsig(key_name, &args.dns01_rfc2136_tsig_algorithm, secret)?;
+            }
+
+            Arc::new(provider)
+        }
+        Some(Dns01Provider::Exec) => {
+            let command = args
+                .dns01_exec_command
+                .clone()
+                .context("dns01_exec_command is not set")?;
+            let timeout = Duration::from_secs(args.dns01_exec_timeout_seconds);
+            Arc::new(ExecProvider::new(command, timeout))
+        }
+        None => anyhow::bail!("dns01_provider is not set"),
+    };
+
+    Ok(ChallengeSolver::Dns01 {
+        provider,
+        propagation_delay: Duration::from_secs(args.dns01_propagation_seconds),
+    })
+}
+
+fn make_acme_settings(args: &Args) -> Result<AcmeSettings, anyhow::Error> {
+    let email = args.tls_email.clone().context("tls_email is not set")?;
+
+    let eab = match (&args.acme_eab_kid, &args.acme_eab_hmac_key) {
+        (Some(key_id), Some(hmac_key)) => {
+            let hmac_key = base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .decode(hmac_key.trim_end_matches('='))
+                .context("acme_eab_hmac_key is not valid base64url")?;
+            Some(ExternalAccountBinding {
+                key_id: key_id.to_string(),
+                hmac_key,
+            })
+        }
+        _ => None,
+    };
+
+    let directory_url = match &args.acme_directory_url {
+        Some(url) => url.to_string(),
+        None => {
+            anyhow::ensure!(
+                eab.is_some() || !args.acme_ca.requires_eab(),
+                "acme_ca {:?} requires acme_eab_kid and acme_eab_hmac_key",
+                args.acme_ca
+            );
+            args.acme_ca.directory_url().to_string()
+        }
+    };
+
+    Ok(AcmeSettings {
+        directory_url,
+        email,
+        eab,
+        preferred_chain: args.acme_preferred_chain.clone(),
+        renew_before: chrono::Duration::days(args.acme_renew_before_days),
+        key_type: args.tls_key_type,
+        key_policy: args.tls_key_policy,
+        account_path: PathBuf::from(CERTS_DIR).join("acme-account.json"),
+    })
+}
+
+fn make_certificate_paths(args: &Args) -> Result<Vec<CertificatePaths>, anyhow::Error> {
+    match args.tls_source {
+        TlsSource::Acme => Ok(certificate_specs(&args.tls_domain, args.tls_cert_mode)
+            .into_iter()
+            .map(|c| c.paths)
+            .collect()),
+        TlsSource::Files => certificate_files(args),
+    }
+}
+
+fn certificate_files(args: &Args) -> Result<Vec<CertificatePaths>, anyhow::Error> {
+    anyhow::ensure!(!args.tls_cert_file.is_empty(), "tls_cert_file is not set");
+    anyhow::ensure!(
+        args.tls_cert_file.len() == args.tls_key_file.len(),
+        "tls_cert_file and tls_key_file must have the same number of files"
+    );
+
+    let paths = args
+        .tls_cert_file
+        .iter()
+        .zip(args.tls_key_file.iter())
+        .map(|(cert, key)| CertificatePaths {
+            cert: cert.clone(),
+            key: key.clone(),
+        })
+        .collect();
+    Ok(paths)
+}
+
+/// Reads the listener acls from an acl file
+async fn load_acl_file(path: &std::path::Path) -> Result<ListenerAcls, anyhow::Error> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("fail to read acl file {}", path.display()))?;
+    ListenerAcls::parse(&content).with_context(|| format!("invalid acl file {}", path.display()))
+}
+
+async fn load_admin_token(path: &std::path::Path) -> Result<String, anyhow::Error> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("fail to read admin token file {}", path.display()))?;
+    let token = content.trim().to_string();
+    anyhow::ensure!(
+        !token.is_empty(),
+        "admin token file {} is empty",
+        path.display()
+    );
+    Ok(token)
+}
+
+/// Re-reads the settings that can change at runtime, and reloads dnsdist with them
+/// Checks that the settings a reload cannot apply are the ones dnsdist-acme started with, as the
+/// console, the certificates and their renewal are set up with them at start
+async fn check_startup_settings(
+    args: &Args,
+    startup: &Args,
+    certificate_paths: &[CertificatePaths],
+    console: &DnsdistConsole,
+) -> Result<(), anyhow::Error> {
+    let mut changed = Vec::new();
+    if args.console_address != startup.console_address {
+        changed.push("console_address");
+    }
+    let key_changed = match &args.console_key_file {
+        Some(path) => load_console_key(path).await? != console.key(),
+        None => startup.console_key_file.is_some(),
+    };
+    if key_changed {
+        changed.push("console_key_file");
+    }
+    if args.tls_enabled != startup.tls_enabled {
+        changed.push("tls_enabled");
+    }
+    if args.tls_enabled && make_certificate_paths(args)? != certificate_paths {
+        changed.push("the tls certificates");
+    }
+
+    anyhow::ensure!(
+        changed.is_empty(),
+        "a reload cannot change {}, restart dnsdist-acme to apply it",
+        changed.join(", ")
+    );
+    Ok(())
+}
+
+async fn reload_dnsdist(
+    args: &Args,
+    startup: &Args,
+    certificate_paths: &[CertificatePaths],
+    console: &DnsdistConsole,
+    reloader: &DnsdistReloader,
+) -> Result<ReloadOutcome, anyhow::Error> {
+    check_startup_settings(args, startup, certificate_paths, console).await?;
+    let file_acls = match &args.acl_file {
+        Some(path) => load_acl_file(path).await?,
+        None => ListenerAcls::default(),
+    };
+    let acls = make_listener_acls(args, &file_acls);
+    let config = make_dnsdist_config(args, certificate_paths, acls, console)?;
+    reloader.reload(config).await
+}
+
+/// Builds the acl of each listener from the args, with the entries of the acl file added
+fn make_listener_acls(args: &Args, file_acls: &ListenerAcls) -> Vec<ListenerAcl> {
+    let mut acls = vec![ListenerAcl {
+        protocol: Protocol::Dns,
+        port: args.port,
+        acl: Acl {
+            allow: args.acl_dns_allow.clone(),
+            deny: args.acl_dns_deny.clone(),
+        },
+    }];
+    if args.tls_enabled {
+        acls.push(ListenerAcl {
+            protocol: Protocol::Doh,
+            port: 443,
+            acl: Acl {
+                allow: args.acl_doh_allow.clone(),
+                deny: args.acl_doh_deny.clone(),
+            },
+        });
+        acls.push(ListenerAcl {
+            protocol: Protocol::Dot,
+            port: 853,
+            acl: Acl {
+                allow: args.acl_dot_allow.clone(),
+                deny: args.acl_dot_deny.clone(),
+            },
+        });
+    }
+
+    for listener in acls.iter_mut() {
+        listener.acl.extend(file_acls.get(listener.protocol));
+    }
+    acls
+}
+
+fn make_restart_settings(args: &Args, policy: RestartPolicy) -> RestartSettings {
+    RestartSettings {
+        policy,
+        initial_backoff: Duration::from_secs(args.restart_backoff_seconds),
+        max_backoff: Duration::from_secs(args.restart_max_backoff_seconds),
+        max_restarts: args.restart_max,
+        window: Duration::from_secs(args.restart_window_seconds),
+    }
+}
+
+fn make_dyn_blocks(args: &Args) -> Option<DynBlocks> {
+    let triggers = [
+        (DynBlockTrigger::Queries, args.dyn_block_qps),
+        (DynBlockTrigger::Nxdomains, args.dyn_block_nxdomain_rate),
+        (DynBlockTrigger::Servfails, args.dyn_block_servfail_rate),
+        (DynBlockTrigger::ResponseBytes, args.dyn_block_bytes_rate),
+    ];
+    let rules: Vec<DynBlockRule> = triggers
+        .into_iter()
+        .filter_map(|(trigger, rate)| {
+            rate.map(|rate| DynBlockRule {
+                trigger,
+                rate,
+                seconds: args.dyn_block_window_seconds,
+                duration: args.dyn_block_duration_seconds,
+            })
+        })
+        .collect();
+    if rules.is_empty() {
+        return None;
+    }
 
+    Some(DynBlocks {
+        rules,
+        v4_prefix: args.dyn_block_v4_prefix,
+        v6_prefix: args.dyn_block_v6_prefix,
+        action: args.dyn_block_action,
+    })
+}
+
+/// Builds the dnsdist config: plain DNS on `port`, and DoH on 443 and DoT on 853 when tls is enabled
+fn make_dnsdist_config(
+    args: &Args,
+    certificates: &[CertificatePaths],
+    acls: Vec<ListenerAcl>,
+    console: &DnsdistConsole,
+) -> Result<DnsdistConfig, anyhow::Error> {
+    anyhow::ensure!(
+        args.backend.iter().any(|b| b.pool.is_none()),
+        "at least one backend must be outside of a pool, to answer the queries that are not routed"
+    );
+    // the acls of the listeners are told apart by their port
+    anyhow::ensure!(
+        !args.tls_enabled || ![443, 853].contains(&args.port),
+        "port {} is used by the DoH and DoT listeners when tls is enabled",
+        args.port
+    );
+    for route in args.route.iter() {
+        anyhow::ensure!(
+            args.backend
+                .iter()
+                .any(|b| b.pool.as_ref() == Some(&route.pool)),
+            "route for {:?} uses pool {}, which has no backends",
+            route.suffixes,
+            route.pool
+        );
+    }
+
+    let hosts: [std::net::IpAddr; 2] = [
+        std::net::Ipv4Addr::UNSPECIFIED.into(),
+        std::net::Ipv6Addr::UNSPECIFIED.into(),
+    ];
+
+    let mut listeners = Vec::new();
+    for host in hosts {
+        listeners.push(Listener::new(
+            SocketAddr::new(host, args.port),
+            ListenerKind::Dns,
+        ));
+    }
+    if args.tls_enabled {
+        for host in hosts {
+            listeners.push(Listener::new(
+                SocketAddr::new(host, 443),
+                ListenerKind::Doh {
+                    certificates: certificates.to_vec(),
+                    paths: vec!["/".to_string(), "/dns-query".to_string()],
+                },
+            ));
+        }
+        for host in hosts {
+            listeners.push(Listener::new(
+                SocketAddr::new(host, 853),
+                ListenerKind::Dot {
+                    certificates: certificates.to_vec(),
+                },
+            ));
+        }
+    }
+
+    // the highest limit goes first, so the heaviest clients get its action
+    let mut rate_limits: Vec<&RateLimit> = args.rate_limit.iter().filter(|l| l.qps > 0).collect();
+    rate_limits.sort_by_key(|l| std::cmp::Reverse(l.qps));
+    let rules = rate_limits
+        .into_iter()
+        .map(|limit| Rule {
+            name: None,
+            selector: Selector::MaxQpsIp {
+                qps: limit.qps,
+                v4_prefix: limit.v4_prefix,
+                v6_prefix: limit.v6_prefix,
+            },
+            action: limit.action.action(),
+        })
+        .collect();
+
+    Ok(DnsdistConfig {
+        listeners,
+        backends: args.backend.clone(),
+        server_policy: args.backend_policy,
+        routes: args.route.clone(),
+        acl: allowed_networks(acls.iter().map(|a| &a.acl)),
+        acls,
+        rules,
+        dyn_blocks: make_dyn_blocks(args),
+        dnstap: Some(Dnstap {
+            identity: "dns".to_string(),
+            socket: PathBuf::from(DNSTAP_SOCKET),
+        }),
+        console: Some(Console {
+            address: console.address(),
+            key: console.key().to_string(),
+        }),
+    })
+}
+
+/// Reloads the certificates of the https server and dnsdist. The servers keep the certificates
+/// they have loaded when a reload fails.
+async fn reload_certificates(
+    config: &RustlsConfig,
+    paths: &[CertificatePaths],
+    console: &DnsdistConsole,
+) {
+    tracing::info!("reloading certs for https server");
+    match tls::load_server_config(paths).await {
+        Ok(server_config) => {
+            config.reload_from_config(server_config);
+            tracing::info!("reloading certs for https server. DONE");
+        }
+        Err(err) => {
+            tracing::error!("reloading certs for https server. ERROR: {err:#}");
+        }
+    }
+
+    tracing::info!("reloading certs for dnsdist server");
+    match console.reload_certificates().await {
+        Ok(()) => tracing::info!("reloading certs for dnsdist server. DONE"),
+        Err(err) => tracing::error!("reloading certs for dnsdist server. ERROR: {err:#}"),
+    }
+}
+
+fn make_service(app_state: AppState) -> IntoMakeServiceWithConnectInfo<Router, SocketAddr> {
     let app = Router::new()
         .route("/logs", get(get_logs))
         .route("/api/logs", get(get_logs_api))
+        .route("/api/tls", get(get_tls_api))
+        .route(
+            "/.well-known/acme-challenge/:token",
+            get(get_acme_challenge),
+        )
         .with_state(app_state)
         .nest_service("/.well-known/", ServeDir::new("./html/.well-known"))
         .layer(RequestBodyTimeoutLayer::new(Duration::from_secs(1)))
@@ -63,6 +899,35 @@ fn make_service(
     app.into_make_service_with_connect_info::<SocketAddr>()
 }
 
+/// How long an admin request may take. A reload can wait for dnsdist to be checked and for a
+/// restarted dnsdist to take over, so it is well above the handover timeout.
+const ADMIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The admin api, which lists the state of dnsdist and its processes, and reloads dnsdist when a
+/// token is set
+fn make_admin_service(
+    app_state: AppState,
+    admin: Option<AdminApi>,
+) -> IntoMakeServiceWithConnectInfo<Router, SocketAddr> {
+    let mut app = Router::new()
+        .route("/api/routes", get(get_routes_api))
+        .route("/api/blocks", get(get_blocks_api))
+        .route("/api/processes", get(get_processes_api))
+        .route("/metrics", get(get_metrics))
+        .with_state(app_state);
+    if let Some(admin) = admin {
+        let reload = Router::new()
+            .route("/api/admin/reload", post(post_reload_api))
+            .with_state(admin);
+        app = app.merge(reload);
+    }
+    let app = app
+        .layer(RequestBodyTimeoutLayer::new(Duration::from_secs(1)))
+        .layer(TimeoutLayer::new(ADMIN_TIMEOUT));
+
+    app.into_make_service_with_connect_info::<SocketAddr>()
+}
+
 async fn sigint() -> std::io::Result<()> {
     signal(SignalKind::interrupt())?.recv().await;
     Ok(())
@@ -77,78 +942,239 @@ async fn sigterm() -> std::io::Result<()> {
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
-    let args = Args::parse();
-    tracing::info!("args: {args:?}");
+    let (args, matches) = match parse_args() {
+        Ok(parsed) => parsed,
+        Err(err) => match err.downcast::<clap::Error>() {
+            Ok(err) => err.exit(),
+            Err(err) => return Err(err),
+        },
+    };
+    let settings = effective_settings(&Args::command(), &matches, NOT_SETTINGS, SECRET_SETTINGS);
+    if let Some(format) = args.print_config {
+        print!("{}", format.render(&settings)?);
+        return Ok(());
+    }
+    tracing::info!("settings: {}", serde_json::Value::Object(settings));
 
     let tracker = TaskTracker::new();
     let token = CancellationToken::new();
 
     let logs_store = QueryLogs::default();
     let usage_stats = UsageStats::default();
+    let challenges = Http01Challenges::default();
 
-    if args.tls_enabled {
-        let domain = args.tls_domain.expect("tls_domain is not set");
-        let email = args.tls_email.expect("tls_email is not set");
+    let certificates = certificate_specs(&args.tls_domain, args.tls_cert_mode);
+    let certificate_paths = make_certificate_paths(&args)?;
+    let file_acls = match &args.acl_file {
+        Some(path) => load_acl_file(path).await?,
+        None => ListenerAcls::default(),
+    };
+    let acls = make_listener_acls(&args, &file_acls);
+    if acls
+        .iter()
+        .any(|a| a.protocol == Protocol::Dns && a.acl.is_open())
+    {
+        tracing::warn!("the DNS53 port accepts queries from every network, set ACL_DNS_ALLOW to keep it from being abused as an open resolver");
+    }
+    anyhow::ensure!(
+        args.console_address.ip().is_loopback(),
+        "the dnsdist console must listen on a loopback address, not {}",
+        args.console_address
+    );
+    let console_key = match &args.console_key_file {
+        Some(path) => load_console_key(path).await?,
+        None => generate_console_key(),
+    };
+    let console = DnsdistConsole::new(args.console_address, console_key);
+    let dnsdist_config = make_dnsdist_config(&args, &certificate_paths, acls, &console)?;
+    if let Some(Commands::CheckConfig) = args.command {
+        // a new private directory, so another user or run cannot place a file or a symlink
+        // where the config is written
+        let dir = std::env::temp_dir().join(format!(
+            "dnsdist-acme-check-{}-{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        tokio::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .await
+            .with_context(|| format!("fail to create {}", dir.display()))?;
+        let res = write_checked_config(&dnsdist_config, &dir.join("dnsdist.conf")).await;
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        res?;
+        println!("dnsdist config OK");
+        return Ok(());
+    }
+    let tls_status = match args.tls_enabled {
+        true => TlsStatus::new(certificate_paths.clone()),
+        false => TlsStatus::default(),
+    };
 
-        let certbot = CertbotTask::new(&domain, &email);
+    anyhow::ensure!(
+        args.admin_address.ip().is_loopback(),
+        "the admin api must listen on a loopback address, not {}",
+        args.admin_address
+    );
+    let admin_token = match &args.admin_token_file {
+        Some(path) => Some(load_admin_token(path).await?),
+        None => None,
+    };
 
-        tracing::info!("certbot obtaining certs");
-        certbot.run().await?;
-        tracing::info!("certbot obtaining certs. DONE");
+    let (dnsdist_process, restarter) = DnsdistProcess::new(console.clone())?;
+    let reloader = DnsdistReloader::new(dnsdist_config.clone(), console.clone(), restarter);
+    let (reload_trigger, mut reload_requests) = ReloadTrigger::new();
+    let supervisor_status = SupervisorStatus::default();
+    let app_state = AppState::new(
+        logs_store.clone(),
+        usage_stats.clone(),
+        challenges.clone(),
+        tls_status.clone(),
+        reloader.clone(),
+        supervisor_status.clone(),
+    );
 
-        let cert = PathBuf::from("./certs/fullchain.pem");
-        let key = PathBuf::from("./certs/privkey.pem");
-        let config_axum = RustlsConfig::from_pem_file(cert.as_path(), key.as_path()).await?;
-        let config_certbot = config_axum.clone();
+    if args.tls_enabled {
+        let acme = match args.tls_source {
+            TlsSource::Acme => {
+                anyhow::ensure!(!args.tls_domain.is_empty(), "tls_domain is not set");
+                let settings = make_acme_settings(&args)?;
+                let solver = make_challenge_solver(&args, challenges)?;
 
-        tracing::info!("Starting certbot auto-update");
-        let cloned_token = token.clone();
-        tracker.spawn(async move {
-            loop {
-                tracing::info!("certbot auto-update sleeping for 1 hour");
-                tokio::select! {
-                    _ = cloned_token.cancelled() => {
-                        tracing::info!("certbot auto-update received cancel signal");
-                        return;
-                    },
-                    _ = tokio::time::sleep(Duration::from_secs(3600)) => {
-                        tracing::info!("certbot auto-update waking up");
-                    },
-                }
+                if args.acme_challenge == AcmeChallenge::Http01 {
+                    let acme_http_port = args.acme_http_port;
+                    tracing::info!("Starting acme http server on port {acme_http_port}");
+                    let cloned_token = token.clone();
+                    let cloned_app_state = app_state.clone();
+                    tracker.spawn(async move {
+                        let addr = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 0], acme_http_port));
+                        let handle = Handle::new();
+                        let server = axum_server::bind(addr).handle(handle.clone());
 
-                tracing::info!("certbot renewing certs");
-                if let Err(err) = certbot.run().await {
-                    tracing::error!("certbot renewing certs. ERROR: {err}");
-                    cloned_token.cancel();
-                    return;
+                        tokio::select! {
+                            _ = cloned_token.cancelled() => {
+                                tracing::info!("acme http server received cancel signal");
+                                handle.shutdown();
+                            },
+                            _ = server.serve(make_service(cloned_app_state)) => {
+                                tracing::info!("acme http server ended prematurely");
+                                cloned_token.cancel();
+                            },
+                        }
+                    });
                 }
-                tracing::info!("certbot renewing certs. DONE");
 
-                tracing::info!("reloading certs for https server");
-                if let Err(err) = config_certbot
-                    .reload_from_pem_file(cert.as_path(), key.as_path())
-                    .await
-                {
-                    tracing::error!("reloading certs for https server. ERROR: {err}");
-                    cloned_token.cancel();
-                    return;
+                Some(AcmeTask::new(certificates, settings, solver)?)
+            }
+            TlsSource::Files => None,
+        };
+
+        // the listeners start with a fallback certificate, and the auto-update below obtains
+        // the real one right away, so a slow or failing ACME server does not hold up startup
+        if let Some(acme) = &acme {
+            let ca = match (&args.tls_fallback_ca_cert, &args.tls_fallback_ca_key) {
+                (Some(cert), Some(key)) => Some(LocalCa::load(cert, key).await?),
+                _ => None,
+            };
+            acme.write_fallbacks(ca.as_ref()).await?;
+        }
+
+        let config_axum = RustlsConfig::from_config(
+            tls::load_server_config(&certificate_paths)
+                .await
+                .context("no usable tls certificate")?,
+        );
+        let config_reload = config_axum.clone();
+        let reload_paths = certificate_paths.clone();
+        let reload_console = console.clone();
+
+        if let Some(acme) = acme {
+            tracing::info!("Starting acme auto-update");
+            let cloned_token = token.clone();
+            let cloned_tls_status = tls_status.clone();
+            tracker.spawn(async move {
+                let mut failures = 0;
+                loop {
+                    let delay = match failures {
+                        0 => acme.time_until_renewal().await,
+                        _ => retry_delay(failures),
+                    };
+                    cloned_tls_status.set_next_renewal(chrono::Utc::now() + delay);
+                    tracing::info!("acme auto-update sleeping for {}s", delay.as_secs());
+                    tokio::select! {
+                        _ = cloned_token.cancelled() => {
+                            tracing::info!("acme auto-update received cancel signal");
+                            return;
+                        },
+                        _ = tokio::time::sleep(delay) => {
+                            tracing::info!("acme auto-update waking up");
+                        },
+                    }
+
+                    tracing::info!("acme renewing certs");
+                    let run_timeout = acme.run_timeout();
+                    let timed = tokio::select! {
+                        _ = cloned_token.cancelled() => {
+                            tracing::info!("acme auto-update received cancel signal");
+                            return;
+                        },
+                        timed = tokio::time::timeout(run_timeout, acme.run()) => timed,
+                    };
+                    let res = timed.unwrap_or_else(|_| {
+                        let secs = run_timeout.as_secs();
+                        Err(anyhow::anyhow!(
+                            "acme renewal did not finish within {secs}s"
+                        ))
+                    });
+                    cloned_tls_status.record_attempt(&res);
+                    match res {
+                        Ok(true) => failures = 0,
+                        Ok(false) => {
+                            failures = 0;
+                            tracing::info!("acme renewing certs. NOT DUE");
+                            continue;
+                        }
+                        Err(err) => {
+                            failures += 1;
+                            tracing::error!("acme renewing certs. ERROR: {err:#}");
+                            continue;
+                        }
+                    }
+                    tracing::info!("acme renewing certs. DONE");
+
+                    reload_certificates(&config_reload, &reload_paths, &reload_console).await;
                 }
-                tracing::info!("reloading certs for https server. DONE");
+            });
+        } else {
+            tracing::info!("Starting tls cert watcher");
+            let cloned_token = token.clone();
+            let interval = Duration::from_secs(args.tls_watch_interval_seconds);
+            let files = reload_paths
+                .iter()
+                .flat_map(|p| [p.cert.clone(), p.key.clone()])
+                .collect();
+            tracker.spawn(async move {
+                let mut watcher = FileWatcher::new(files).await;
+                loop {
+                    tokio::select! {
+                        _ = cloned_token.cancelled() => {
+                            tracing::info!("tls cert watcher received cancel signal");
+                            return;
+                        },
+                        _ = tokio::time::sleep(interval) => {},
+                    }
 
-                tracing::info!("reloading certs for dnsdist server");
-                if let Err(err) = run_dnsdist_reload_cert().await {
-                    tracing::error!("reloading certs for dnsdist server. ERROR: {err}");
-                    cloned_token.cancel();
-                    return;
+                    if watcher.poll().await {
+                        tracing::info!("tls cert files changed");
+                        reload_certificates(&config_reload, &reload_paths, &reload_console).await;
+                    }
                 }
-                tracing::info!("reloading certs for dnsdist server. DONE");
-            }
-        });
+            });
+        }
 
         tracing::info!("Starting https server on port 8443");
         let cloned_token = token.clone();
-        let cloned_logs_store = logs_store.clone();
-        let cloned_usage_stats = usage_stats.clone();
+        let cloned_app_state = app_state.clone();
         tracker.spawn(async move {
             let addr = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 0], 8443));
             let handle = Handle::new();
@@ -159,7 +1185,7 @@ async fn main() -> anyhow::Result<()> {
                     tracing::info!("https server received cancel signal");
                     handle.shutdown();
                 },
-                _ = server.serve(make_service(cloned_logs_store, cloned_usage_stats)) => {
+                _ = server.serve(make_service(cloned_app_state)) => {
                     tracing::info!("https server ended prematurely");
                     cloned_token.cancel();
                 },
@@ -169,8 +1195,7 @@ async fn main() -> anyhow::Result<()> {
 
     tracing::info!("Starting http server on port 8080");
     let cloned_token = token.clone();
-    let cloned_logs_store = logs_store.clone();
-    let cloned_usage_stats = usage_stats.clone();
+    let cloned_app_state = app_state.clone();
     tracker.spawn(async move {
         let addr = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 0], 8080));
         let handle = Handle::new();
@@ -181,43 +1206,74 @@ async fn main() -> anyhow::Result<()> {
                 tracing::info!("http server received cancel signal");
                 handle.shutdown();
             },
-            _ = server.serve(make_service(cloned_logs_store, cloned_usage_stats)) => {
+            _ = server.serve(make_service(cloned_app_state)) => {
                 tracing::info!("http server ended prematurely");
                 cloned_token.cancel();
             },
         }
     });
 
-    tracing::info!("Starting dnstap");
+    let admin_address = args.admin_address;
+    tracing::info!("Starting admin server on {admin_address}");
     let cloned_token = token.clone();
+    let cloned_app_state = app_state.clone();
+    let admin = admin_token.map(|admin_token| AdminApi::new(admin_token, reload_trigger.clone()));
     tracker.spawn(async move {
-        let mut child = match spawn_dnstap() {
-            Ok(child) => child,
-            Err(err) => {
-                tracing::error!("Starting dnstap. ERROR: {err}");
-                cloned_token.cancel();
-                return;
-            }
-        };
+        let handle = Handle::new();
+        let server = axum_server::bind(admin_address).handle(handle.clone());
 
         tokio::select! {
             _ = cloned_token.cancelled() => {
-                tracing::info!("dnstap received cancel signal");
-                let _ = child.kill().await;
+                tracing::info!("admin server received cancel signal");
+                handle.shutdown();
             },
-            _ = child.wait() => {
-                tracing::info!("dnstap ended prematurely");
+            _ = server.serve(make_admin_service(cloned_app_state, admin)) => {
+                tracing::info!("admin server ended prematurely");
                 cloned_token.cancel();
             },
         }
     });
 
+    let (logs_sender, logs_receiver) = tokio::sync::mpsc::channel(DNSTAP_CHANNEL_CAPACITY);
+
+    tracing::info!("Starting dnstap listener");
+    let cloned_token = token.clone();
+    let cloned_usage_stats = usage_stats.clone();
+    let supervisor = Supervisor::new(
+        "dnstap listener",
+        make_restart_settings(&args, args.dnstap_restart_policy),
+        supervisor_status.clone(),
+    );
+    tracker.spawn(async move {
+        let res = supervisor
+            .run(&cloned_token, || {
+                run_dnstap_listener(
+                    DNSTAP_SOCKET,
+                    logs_sender.clone(),
+                    cloned_usage_stats.clone(),
+                )
+            })
+            .await;
+        if cloned_token.is_cancelled() {
+            tracing::info!("dnstap listener received cancel signal");
+            return;
+        }
+        // dns resolution goes on without the query logs
+        match res {
+            Ok(()) => tracing::error!("dnstap listener stopped, query logs are not collected"),
+            Err(err) => tracing::error!(
+                "dnstap listener stopped, query logs are not collected. ERROR: {err:#}"
+            ),
+        }
+    });
+
     tracing::info!("Starting logs_consumer read_logs");
     let cloned_token = token.clone();
     let cloned_logs_store = logs_store.clone();
     let cloned_usage_stats = usage_stats.clone();
     tracker.spawn(async move {
-        let log_consumer = LogsConsumer::new(cloned_logs_store, cloned_usage_stats);
+        let mut log_consumer =
+            LogsConsumer::new(cloned_logs_store, cloned_usage_stats, logs_receiver);
         loop {
             tracing::info!("logs_consumer read_logs logs-cleanup sleeping for 1 second");
             tokio::select! {
@@ -231,33 +1287,127 @@ async fn main() -> anyhow::Result<()> {
             }
 
             tracing::info!("Reading logs");
-            log_consumer.ingest_logs_from_file().await;
+            log_consumer.ingest_logs().await;
             tracing::info!("Reading logs. DONE");
         }
     });
 
-    tracing::info!("Starting dnsdist server");
+    tracing::info!("Starting dnsdist reloader");
     let cloned_token = token.clone();
+    let cloned_paths = certificate_paths.clone();
+    let cloned_console = console.clone();
+    let startup_args = args.clone();
     tracker.spawn(async move {
-        let mut child = match spawn_dnsdist(args.tls_enabled, args.backend, args.port) {
-            Ok(child) => child,
+        loop {
+            let reply = tokio::select! {
+                _ = cloned_token.cancelled() => {
+                    tracing::info!("dnsdist reloader received cancel signal");
+                    return;
+                },
+                Some(reply) = reload_requests.recv() => reply,
+            };
+
+            tracing::info!("reloading dnsdist server");
+            // the config file is read again, the command line and the environment stay the same
+            let res = match parse_args() {
+                Ok((args, _)) => {
+                    reload_dnsdist(
+                        &args,
+                        &startup_args,
+                        &cloned_paths,
+                        &cloned_console,
+                        &reloader,
+                    )
+                    .await
+                }
+                Err(err) => Err(err),
+            };
+            match &res {
+                Ok(outcome) => tracing::info!("reloading dnsdist server. DONE: {outcome:?}"),
+                Err(err) => tracing::error!(
+                    "reloading dnsdist server, keeping the current config. ERROR: {err:#}"
+                ),
+            }
+            let _ = reply.send(res);
+        }
+    });
+
+    tracing::info!("Starting sighup listener");
+    let cloned_token = token.clone();
+    let cloned_trigger = reload_trigger.clone();
+    tracker.spawn(async move {
+        let mut sighup = match signal(SignalKind::hangup()) {
+            Ok(sighup) => sighup,
             Err(err) => {
-                tracing::error!("Starting dnsdist server. ERROR: {err}");
-                cloned_token.cancel();
+                tracing::error!("Unable to listen for sighup signal: {err}");
                 return;
             }
         };
+        loop {
+            tokio::select! {
+                _ = cloned_token.cancelled() => {
+                    tracing::info!("sighup listener received cancel signal");
+                    return;
+                },
+                _ = sighup.recv() => {
+                    tracing::info!("Received sighup signal");
+                    // the reloader logs the outcome
+                    let _ = cloned_trigger.reload().await;
+                },
+            }
+        }
+    });
 
-        tokio::select! {
-            _ = cloned_token.cancelled() => {
-                tracing::info!("dnsdist server received cancel signal");
-                let _ = child.kill().await;
-            },
-            _ = child.wait() => {
-                tracing::info!("dnsdist server ended prematurely");
-                cloned_token.cancel();
-            },
+    if let Some(acl_file) = args.acl_file.clone() {
+        tracing::info!("Starting acl file watcher");
+        let cloned_token = token.clone();
+        let cloned_trigger = reload_trigger.clone();
+        let interval = Duration::from_secs(args.acl_watch_interval_seconds);
+        tracker.spawn(async move {
+            let mut watcher = FileWatcher::new(vec![acl_file.clone()]).await;
+            loop {
+                tokio::select! {
+                    _ = cloned_token.cancelled() => {
+                        tracing::info!("acl file watcher received cancel signal");
+                        return;
+                    },
+                    _ = tokio::time::sleep(interval) => {},
+                }
+
+                if watcher.poll().await {
+                    tracing::info!("acl file changed");
+                    let _ = cloned_trigger.reload().await;
+                }
+            }
+        });
+    }
+
+    tracing::info!("Starting dnsdist server");
+    let cloned_token = token.clone();
+    let supervisor = Supervisor::new(
+        "dnsdist server",
+        make_restart_settings(&args, args.dnsdist_restart_policy),
+        supervisor_status.clone(),
+    );
+    tracker.spawn(async move {
+        if let Err(err) = install_config(&dnsdist_config).await {
+            tracing::error!("Checking dnsdist config. ERROR: {err:#}");
+            cloned_token.cancel();
+            return;
+        }
+
+        let res = supervisor
+            .run(&cloned_token, || dnsdist_process.run())
+            .await;
+        if cloned_token.is_cancelled() {
+            tracing::info!("dnsdist server received cancel signal");
+            return;
+        }
+        if let Err(err) = res {
+            tracing::error!("dnsdist server. ERROR: {err:#}");
         }
+        tracing::info!("dnsdist server ended prematurely");
+        cloned_token.cancel();
     });
 
     tracker.close();
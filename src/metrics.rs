@@ -0,0 +1,212 @@
+use std::fmt::Write;
+
+use crate::tls::TlsStatusOutput;
+
+/// Writes metrics in the prometheus text exposition format
+#[derive(Default)]
+pub struct MetricsWriter {
+    output: String,
+}
+
+impl MetricsWriter {
+    /// Writes the HELP and TYPE lines that precede the samples of a metric
+    pub fn describe(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.output, "# HELP {name} {help}");
+        let _ = writeln!(self.output, "# TYPE {name} {kind}");
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.output.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{k}=\"{}\"", escape_label(v)))
+                .collect();
+            let _ = write!(self.output, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.output, " {value}");
+    }
+
+    pub fn finish(self) -> String {
+        self.output
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub fn render_metrics(tls: &TlsStatusOutput, active_ips: usize, invalid_logs: usize) -> String {
+    let mut w = MetricsWriter::default();
+
+    w.describe(
+        "dnsdist_acme_active_ips",
+        "gauge",
+        "Client ips that sent a query in the last 10 minutes",
+    );
+    w.sample("dnsdist_acme_active_ips", &[], active_ips as f64);
+
+    w.describe(
+        "dnsdist_acme_invalid_logs_total",
+        "counter",
+        "Dnstap messages that could not be parsed into query logs",
+    );
+    w.sample("dnsdist_acme_invalid_logs_total", &[], invalid_logs as f64);
+
+    w.describe(
+        "dnsdist_acme_tls_enabled",
+        "gauge",
+        "Whether DoH and DoT are enabled",
+    );
+    w.sample("dnsdist_acme_tls_enabled", &[], tls.enabled as u8 as f64);
+
+    let certificates: Vec<_> = tls
+        .certificates
+        .iter()
+        .filter_map(|c| c.info.as_ref().map(|info| (c.path.as_str(), info)))
+        .collect();
+
+    w.describe(
+        "dnsdist_acme_certificate_info",
+        "gauge",
+        "Subject, issuer and names of the served certificate",
+    );
+    for (path, info) in certificates.iter() {
+        let names = info.names.join(",");
+        let labels = [
+            ("path", *path),
+            ("subject", info.subject.as_str()),
+            ("issuer", info.issuer.as_str()),
+            ("names", names.as_str()),
+        ];
+        w.sample("dnsdist_acme_certificate_info", &labels, 1.0);
+    }
+
+    w.describe(
+        "dnsdist_acme_certificate_not_before_seconds",
+        "gauge",
+        "Start of the certificate validity, in unix time",
+    );
+    for (path, info) in certificates.iter() {
+        let value = info.not_before.timestamp() as f64;
+        w.sample(
+            "dnsdist_acme_certificate_not_before_seconds",
+            &[("path", path)],
+            value,
+        );
+    }
+
+    w.describe(
+        "dnsdist_acme_certificate_not_after_seconds",
+        "gauge",
+        "End of the certificate validity, in unix time",
+    );
+    for (path, info) in certificates.iter() {
+        let value = info.not_after.timestamp() as f64;
+        w.sample(
+            "dnsdist_acme_certificate_not_after_seconds",
+            &[("path", path)],
+            value,
+        );
+    }
+
+    w.describe(
+        "dnsdist_acme_certificate_days_remaining",
+        "gauge",
+        "Whole days until the certificate expires",
+    );
+    for (path, info) in certificates.iter() {
+        let value = info.days_remaining() as f64;
+        w.sample(
+            "dnsdist_acme_certificate_days_remaining",
+            &[("path", path)],
+            value,
+        );
+    }
+
+    if let Some(attempt) = &tls.last_renewal {
+        w.describe(
+            "dnsdist_acme_renewal_last_attempt_seconds",
+            "gauge",
+            "Time of the last renewal attempt, in unix time",
+        );
+        let value = attempt.time.timestamp() as f64;
+        w.sample("dnsdist_acme_renewal_last_attempt_seconds", &[], value);
+
+        w.describe(
+            "dnsdist_acme_renewal_last_success",
+            "gauge",
+            "Whether the last renewal attempt succeeded",
+        );
+        let value = attempt.success as u8 as f64;
+        w.sample("dnsdist_acme_renewal_last_success", &[], value);
+    }
+
+    w.describe(
+        "dnsdist_acme_renewal_failures_total",
+        "counter",
+        "Failed renewal attempts since start",
+    );
+    let value = tls.renewal_failures as f64;
+    w.sample("dnsdist_acme_renewal_failures_total", &[], value);
+
+    if let Some(next_renewal) = tls.next_renewal {
+        w.describe(
+            "dnsdist_acme_renewal_next_seconds",
+            "gauge",
+            "Time of the next scheduled renewal check, in unix time",
+        );
+        let value = next_renewal.timestamp() as f64;
+        w.sample("dnsdist_acme_renewal_next_seconds", &[], value);
+    }
+
+    w.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::render_metrics;
+    use crate::certs::CertificateInfo;
+    use crate::tls::{CertificateStatus, TlsStatusOutput};
+
+    #[test]
+    fn test_render_metrics() {
+        let not_after = Utc::now() + Duration::days(45) + Duration::hours(1);
+        let tls = TlsStatusOutput {
+            enabled: true,
+            certificates: vec![CertificateStatus {
+                path: "./certs/fullchain.pem".to_string(),
+                info: Some(CertificateInfo {
+                    subject: "CN=dns.example.com".to_string(),
+                    issuer: "CN=\"Test\" CA".to_string(),
+                    names: vec!["dns.example.com".to_string(), "example.com".to_string()],
+                    not_before: not_after - Duration::days(90),
+                    not_after,
+                }),
+                days_remaining: Some(45),
+                fallback: false,
+                error: None,
+            }],
+            renewal_failures: 2,
+            ..Default::default()
+        };
+
+        let output = render_metrics(&tls, 3, 0);
+        assert!(
+            output.contains("# TYPE dnsdist_acme_active_ips gauge\ndnsdist_acme_active_ips 3\n")
+        );
+        assert!(output.contains(
+            "dnsdist_acme_certificate_info{path=\"./certs/fullchain.pem\",subject=\"CN=dns.example.com\",issuer=\"CN=\\\"Test\\\" CA\",names=\"dns.example.com,example.com\"} 1\n"
+        ));
+        assert!(output.contains(
+            "dnsdist_acme_certificate_days_remaining{path=\"./certs/fullchain.pem\"} 45\n"
+        ));
+        assert!(output.contains("dnsdist_acme_renewal_failures_total 2\n"));
+        assert!(!output.contains("dnsdist_acme_renewal_next_seconds"));
+    }
+}
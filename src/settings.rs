@@ -0,0 +1,291 @@
+use std::{collections::BTreeMap, ffi::OsString, path::Path};
+
+use anyhow::{anyhow, bail, Context};
+use clap::{error::ErrorKind, parser::ValueSource, Arg, ArgAction, ArgMatches, Command, ValueEnum};
+use serde_json::{Map, Value};
+
+/// The formats of the config file. The file is read as TOML when its name ends with `.toml`, and
+/// as YAML otherwise, which also reads JSON.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+}
+
+impl ConfigFormat {
+    fn of_path(path: &Path) -> Self {
+        match path.extension().is_some_and(|e| e == "toml") {
+            true => ConfigFormat::Toml,
+            false => ConfigFormat::Yaml,
+        }
+    }
+
+    pub fn render(&self, settings: &Map<String, Value>) -> Result<String, anyhow::Error> {
+        let rendered = match self {
+            ConfigFormat::Yaml => serde_yaml::to_string(settings)?,
+            ConfigFormat::Toml => toml::to_string(settings)?,
+        };
+        Ok(rendered)
+    }
+}
+
+/// The args that are settings, which is every long option except help, version and `ignored`
+fn settings<'a>(command: &'a Command, ignored: &'a [&str]) -> impl Iterator<Item = &'a Arg> {
+    command.get_arguments().filter(|arg| {
+        arg.get_long().is_some()
+            && !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version)
+            && !ignored.contains(&arg.get_id().as_str())
+    })
+}
+
+/// The message of a clap error, without its `error:` prefix and the usage hint
+fn clap_message(err: &clap::Error) -> String {
+    let rendered = err.to_string();
+    let message = rendered.strip_prefix("error: ").unwrap_or(&rendered);
+    let message = message.split("\n\n").next().unwrap_or(message);
+    message.trim().to_string()
+}
+
+fn read_config_file(path: &Path) -> Result<BTreeMap<String, Value>, anyhow::Error> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("fail to read config file {}", path.display()))?;
+    let values: Option<BTreeMap<String, Value>> = match ConfigFormat::of_path(path) {
+        ConfigFormat::Yaml => serde_yaml::from_str(&content)?,
+        ConfigFormat::Toml => Some(toml::from_str(&content)?),
+    };
+
+    Ok(values.unwrap_or_default())
+}
+
+/// Turns a value of the config file into the values of its arg. Settings that take several
+/// values take lists.
+fn arg_values(arg: &Arg, value: &Value) -> Result<Vec<String>, anyhow::Error> {
+    let values = match value {
+        Value::Array(items) if matches!(arg.get_action(), ArgAction::Append) => {
+            items.iter().collect()
+        }
+        Value::Array(_) => bail!("expected a single value, not a list"),
+        value => vec![value],
+    };
+
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::String(s) => Ok(s.clone()),
+            Value::Number(n) => Ok(n.to_string()),
+            Value::Bool(b) => Ok(b.to_string()),
+            _ => bail!("expected a string, a number or a boolean"),
+        })
+        .collect()
+}
+
+/// Checks the values of a setting by parsing its options on their own. The settings it requires
+/// are checked once all the settings are parsed together.
+fn check_options(command: &Command, options: &[OsString]) -> Result<(), anyhow::Error> {
+    let argv = std::iter::once(OsString::from(command.get_name())).chain(options.iter().cloned());
+    match command.clone().try_get_matches_from(argv) {
+        Err(err) if err.kind() != ErrorKind::MissingRequiredArgument => bail!(clap_message(&err)),
+        _ => Ok(()),
+    }
+}
+
+/// The command line options that set the values of the config file, except the ones already set
+/// on the command line or in the environment
+fn config_file_options(
+    command: &Command,
+    matches: &ArgMatches,
+    path: &Path,
+    ignored: &[&str],
+) -> Result<Vec<OsString>, anyhow::Error> {
+    let args: BTreeMap<&str, &Arg> = settings(command, ignored)
+        .map(|arg| (arg.get_id().as_str(), arg))
+        .collect();
+
+    let mut options = Vec::new();
+    for (key, value) in read_config_file(path)? {
+        let name = key.replace('-', "_");
+        let Some(arg) = args.get(name.as_str()) else {
+            bail!("unknown setting `{key}`");
+        };
+        let long = arg.get_long().unwrap_or_default();
+        if value.is_null()
+            || matches!(
+                matches.value_source(&name),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        {
+            continue;
+        }
+
+        if matches!(arg.get_action(), ArgAction::SetTrue) {
+            match value {
+                Value::Bool(true) => options.push(format!("--{long}").into()),
+                Value::Bool(false) => {}
+                _ => bail!("invalid `{key}`: expected true or false"),
+            }
+            continue;
+        }
+
+        let values = arg_values(arg, &value).with_context(|| format!("invalid `{key}`"))?;
+        let arg_options: Vec<OsString> = values
+            .iter()
+            .map(|v| format!("--{long}={v}").into())
+            .collect();
+        check_options(command, &arg_options).with_context(|| format!("invalid `{key}`"))?;
+        options.extend(arg_options);
+    }
+
+    Ok(options)
+}
+
+/// Parses `argv` with the settings of the config file named by the `config_arg` arg underneath.
+/// The command line goes first, then the environment, then the config file, then the defaults.
+/// The settings are named after their arg ids, `ignored` args cannot be set in the file. Errors
+/// of the command line and the environment are returned as `clap::Error`s.
+pub fn parse_with_config_file(
+    command: Command,
+    argv: Vec<OsString>,
+    config_arg: &str,
+    ignored: &[&str],
+) -> Result<ArgMatches, anyhow::Error> {
+    let matches = command.clone().try_get_matches_from(argv.iter())?;
+    let Some(path) = matches.get_one::<std::path::PathBuf>(config_arg) else {
+        return Ok(matches);
+    };
+
+    let mut ignored = ignored.to_vec();
+    ignored.push(config_arg);
+    let options = config_file_options(&command, &matches, path, &ignored)
+        .with_context(|| format!("invalid config file {}", path.display()))?;
+
+    let argv = argv
+        .iter()
+        .take(1)
+        .chain(&options)
+        .chain(argv.iter().skip(1));
+    command
+        .try_get_matches_from(argv)
+        .map_err(|err| anyhow!(clap_message(&err)))
+        .with_context(|| format!("invalid config file {}", path.display()))
+}
+
+/// The settings in effect, by name, for printing them. Unset settings are left out, and the
+/// values of the `secrets` are redacted.
+pub fn effective_settings(
+    command: &Command,
+    matches: &ArgMatches,
+    ignored: &[&str],
+    secrets: &[&str],
+) -> Map<String, Value> {
+    let mut effective = Map::new();
+    for arg in settings(command, ignored) {
+        let id = arg.get_id().as_str();
+        let Some(raw) = matches.get_raw(id) else {
+            continue;
+        };
+
+        let values: Vec<Value> = raw
+            .map(|value| match secrets.contains(&id) {
+                true => Value::String("<redacted>".to_string()),
+                false => typed_value(&value.to_string_lossy()),
+            })
+            .collect();
+        let value = match arg.get_action() {
+            ArgAction::Append => Value::Array(values),
+            _ => values.into_iter().next().unwrap_or(Value::Null),
+        };
+        effective.insert(id.to_string(), value);
+    }
+
+    effective
+}
+
+fn typed_value(value: &str) -> Value {
+    if let Ok(b) = value.parse::<bool>() {
+        return Value::Bool(b);
+    }
+    if let Ok(n) = value.parse::<u64>() {
+        return n.into();
+    }
+    if let Ok(n) = value.parse::<i64>() {
+        return n.into();
+    }
+    Value::String(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::PathBuf};
+
+    use clap::{CommandFactory, Parser};
+
+    use super::{effective_settings, parse_with_config_file, ConfigFormat};
+
+    #[derive(Parser, Debug)]
+    struct TestArgs {
+        #[arg(long, default_value = "53")]
+        port: u16,
+        #[arg(long, value_delimiter = ',', default_value = "8.8.8.8:53")]
+        backend: Vec<String>,
+        #[arg(long)]
+        tls_enabled: bool,
+        #[arg(long)]
+        tls_email: Option<String>,
+        #[arg(long)]
+        secret: Option<String>,
+        #[arg(long)]
+        config_file: Option<PathBuf>,
+    }
+
+    fn parse(config: &str, file_name: &str, args: &[&str]) -> Result<TestArgs, anyhow::Error> {
+        let path = std::env::temp_dir().join(format!("{}-{file_name}", std::process::id()));
+        std::fs::write(&path, config).unwrap();
+
+        let mut argv: Vec<OsString> = vec!["test".into(), "--config-file".into(), path.into()];
+        argv.extend(args.iter().map(OsString::from));
+        let matches = parse_with_config_file(TestArgs::command(), argv, "config_file", &[])?;
+
+        let effective = effective_settings(
+            &TestArgs::command(),
+            &matches,
+            &["config_file"],
+            &["secret"],
+        );
+        assert!(!effective.contains_key("config_file"));
+        assert!(!effective.contains_key("tls_email"));
+        let rendered = ConfigFormat::Yaml.render(&effective).unwrap();
+        assert!(!rendered.contains("hunter2"), "{rendered}");
+
+        Ok(<TestArgs as clap::FromArgMatches>::from_arg_matches(
+            &matches,
+        )?)
+    }
+
+    #[test]
+    fn test_parse_with_config_file() {
+        let config = "port: 5353\nbackend:\n  - 1.1.1.1:53\n  - 9.9.9.9:53\ntls-enabled: true\nsecret: hunter2\n";
+        let args = parse(config, "settings.yaml", &["--port", "53"]).unwrap();
+        assert_eq!(args.port, 53, "the command line goes first");
+        assert_eq!(args.backend, ["1.1.1.1:53", "9.9.9.9:53"]);
+        assert!(args.tls_enabled);
+        assert_eq!(args.secret.as_deref(), Some("hunter2"));
+
+        let config = "port = 5353\nbackend = \"1.1.1.1:53,9.9.9.9:53\"\n";
+        let args = parse(config, "settings.toml", &[]).unwrap();
+        assert_eq!(args.port, 5353);
+        assert_eq!(args.backend, ["1.1.1.1:53", "9.9.9.9:53"]);
+        assert!(!args.tls_enabled);
+
+        let err = parse("prot: 5353\n", "unknown.yaml", &[]).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err).split(": ").last(),
+            Some("unknown setting `prot`")
+        );
+
+        let err = parse("port: [53]\n", "list.yaml", &[]).unwrap_err();
+        assert!(format!("{err:#}").contains("invalid `port`: expected a single value"));
+
+        let err = parse("port: dns\n", "invalid.yaml", &[]).unwrap_err();
+        assert!(format!("{err:#}").contains("invalid `port`: invalid value 'dns'"));
+    }
+}
@@ -0,0 +1,270 @@
+use std::path::PathBuf;
+
+use anyhow::bail;
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use rcgen::KeyPair;
+
+use crate::acme::{
+    load_or_create_account, AcmeClient, AcmeHttpClient, ChallengeSolver, ExternalAccountBinding,
+};
+use crate::certs::{
+    write_certificate, write_fallback_certificate, CertificateInfo, CertificateSpec, KeyPolicy,
+    KeyType, LocalCa,
+};
+
+const RETRY_INITIAL_DELAY: std::time::Duration = std::time::Duration::from_secs(60);
+const RETRY_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(6 * 3600);
+
+/// The certificates are checked at least this often, so changes made outside of the task are noticed
+const MAX_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(24 * 3600);
+
+/// The certificates are not checked more often than this while they are usable
+const MIN_CHECK_INTERVAL: Duration = Duration::hours(1);
+
+/// How long the order of a certificate may take, besides waiting for dns-01 records to propagate
+const ORDER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(600);
+
+#[derive(Debug, Clone)]
+pub struct AcmeSettings {
+    pub directory_url: String,
+    pub email: String,
+    pub eab: Option<ExternalAccountBinding>,
+    pub preferred_chain: Option<String>,
+    pub renew_before: Duration,
+    pub key_type: KeyType,
+    pub key_policy: KeyPolicy,
+    pub account_path: PathBuf,
+}
+
+pub struct AcmeTask {
+    certificates: Vec<CertificateSpec>,
+    settings: AcmeSettings,
+    solver: ChallengeSolver,
+    http: AcmeHttpClient,
+}
+
+impl AcmeTask {
+    pub fn new(
+        certificates: Vec<CertificateSpec>,
+        settings: AcmeSettings,
+        solver: ChallengeSolver,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            certificates,
+            settings,
+            solver,
+            http: AcmeHttpClient::new()?,
+        })
+    }
+
+    /// Writes a fallback certificate in place of every certificate that cannot be loaded,
+    /// they are served until `run` obtains the real ones
+    pub async fn write_fallbacks(&self, ca: Option<&LocalCa>) -> Result<(), anyhow::Error> {
+        for spec in self.certificates.iter() {
+            if CertificateInfo::load(&spec.paths.cert).await.is_ok() {
+                continue;
+            }
+
+            tracing::info!("acme writing fallback certificate for {:?}", spec.domains);
+            write_fallback_certificate(spec, self.settings.key_type, ca).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads the certificate and key obtained by a previous run. Fallback certificates and
+    /// keys of another type are not returned, so they are replaced.
+    async fn load_current(&self, spec: &CertificateSpec) -> Option<(CertificateInfo, KeyPair)> {
+        let info = CertificateInfo::load(&spec.paths.cert).await.ok()?;
+        if info.is_fallback() {
+            return None;
+        }
+
+        let key_pem = tokio::fs::read_to_string(&spec.paths.key).await.ok()?;
+        let key_pair = KeyPair::from_pem(&key_pem).ok()?;
+        if KeyType::of(&key_pair) != Some(self.settings.key_type) {
+            return None;
+        }
+
+        Some((info, key_pair))
+    }
+
+    /// How long before its expiry a certificate is renewed. It is at most a third of its
+    /// lifetime, so a CA that issues shorter lived certificates does not make them due at once.
+    fn renew_before(&self, info: &CertificateInfo) -> Duration {
+        let lifetime = info.not_after - info.not_before;
+        self.settings.renew_before.min(lifetime / 3)
+    }
+
+    /// Returns when the first certificate is due for renewal, now if one is missing
+    pub async fn next_renewal(&self) -> DateTime<Utc> {
+        let mut next = None;
+        for spec in self.certificates.iter() {
+            let due = match self.load_current(spec).await {
+                Some((info, _)) if info.is_usable_for(&spec.domains, Duration::zero()) => {
+                    let due = info.not_after - self.renew_before(&info);
+                    due.max(Utc::now() + MIN_CHECK_INTERVAL)
+                }
+                _ => Utc::now(),
+            };
+            next = Some(next.map_or(due, |n: DateTime<Utc>| n.min(due)));
+        }
+        next.unwrap_or_else(Utc::now)
+    }
+
+    /// Returns how long to sleep until the next renewal check
+    pub async fn time_until_renewal(&self) -> std::time::Duration {
+        let delay = self.next_renewal().await - Utc::now();
+        delay.to_std().unwrap_or_default().min(MAX_CHECK_INTERVAL)
+    }
+
+    /// How long `run` may take to order every certificate
+    pub fn run_timeout(&self) -> std::time::Duration {
+        let propagation_delay = match &self.solver {
+            ChallengeSolver::Dns01 {
+                propagation_delay, ..
+            } => *propagation_delay,
+            ChallengeSolver::Http01(_) => std::time::Duration::ZERO,
+        };
+        (ORDER_TIMEOUT + propagation_delay) * self.certificates.len().max(1) as u32
+    }
+
+    /// Obtains every certificate that is missing or close to expiring, and returns true if any
+    /// was written. A failed certificate does not stop the others from being renewed, the
+    /// failures are reported together.
+    pub async fn run(&self) -> Result<bool, anyhow::Error> {
+        let mut renewed = false;
+        let mut failed = Vec::new();
+        for spec in self.certificates.iter() {
+            match self.run_certificate(spec).await {
+                Ok(res) => renewed |= res,
+                Err(err) => {
+                    tracing::error!("acme certificate for {:?}. ERROR: {err:#}", spec.domains);
+                    failed.push(spec.domains.join(","));
+                }
+            }
+        }
+
+        if !failed.is_empty() {
+            bail!("fail to obtain certificates for {}", failed.join(" "));
+        }
+
+        Ok(renewed)
+    }
+
+    async fn run_certificate(&self, spec: &CertificateSpec) -> Result<bool, anyhow::Error> {
+        let current = self.load_current(spec).await;
+        if let Some((info, _)) = &current {
+            if info.is_usable_for(&spec.domains, self.renew_before(info)) {
+                tracing::info!(
+                    "acme certificate for {:?} is valid until {}, skipping",
+                    spec.domains,
+                    info.not_after
+                );
+                return Ok(false);
+            }
+        }
+
+        let key_pair = match (self.settings.key_policy, current) {
+            (KeyPolicy::Reuse, Some((_, key_pair))) => {
+                tracing::info!("acme reusing the private key of {:?}", spec.domains);
+                key_pair
+            }
+            _ => self.settings.key_type.generate()?,
+        };
+
+        let account = load_or_create_account(
+            &self.settings.account_path,
+            &self.settings.directory_url,
+            &self.settings.email,
+            self.settings.eab.as_ref(),
+            &self.http,
+        )
+        .await?;
+
+        let client = AcmeClient::new(
+            account,
+            self.http.clone(),
+            self.settings.preferred_chain.clone(),
+        );
+        let cert = client
+            .obtain_certificate(&spec.domains, &self.solver, &key_pair)
+            .await?;
+
+        write_certificate(&spec.paths, &cert.cert_chain_pem, &cert.private_key_pem).await?;
+
+        Ok(true)
+    }
+}
+
+/// Returns the delay before retrying after `failures` consecutive failures. The delay doubles up
+/// to a maximum, and is randomized to between half and all of it, so that many instances that
+/// failed together do not retry together.
+pub fn retry_delay(failures: u32) -> std::time::Duration {
+    let exponent = failures.saturating_sub(1).min(16);
+    let delay = RETRY_INITIAL_DELAY
+        .saturating_mul(1 << exponent)
+        .min(RETRY_MAX_DELAY);
+
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::{retry_delay, AcmeSettings, AcmeTask, RETRY_INITIAL_DELAY, RETRY_MAX_DELAY};
+    use crate::acme::{ChallengeSolver, Http01Challenges};
+    use crate::certs::{CertificateInfo, CertificatePaths, CertificateSpec, KeyPolicy, KeyType};
+
+    #[test]
+    fn test_retry_delay() {
+        for failures in 1..40 {
+            let delay = retry_delay(failures);
+            let max = RETRY_INITIAL_DELAY
+                .saturating_mul(1 << (failures - 1).min(16))
+                .min(RETRY_MAX_DELAY);
+            assert!(delay <= max, "{failures}: {delay:?} > {max:?}");
+            assert!(delay >= max / 2, "{failures}: {delay:?} < {:?}", max / 2);
+        }
+    }
+
+    /// Runs an order against a pebble started with `PEBBLE_VA_ALWAYS_VALID=1`, so the challenge
+    /// needs no server. `SSL_CERT_FILE` must point at its `pebble.minica.pem`.
+    #[tokio::test]
+    #[ignore]
+    async fn test_pebble_order() {
+        let directory_url = std::env::var("PEBBLE_DIRECTORY_URL")
+            .unwrap_or_else(|_| "https://localhost:14000/dir".to_string());
+        let dir = std::env::temp_dir().join(format!("pebble-test-{}", rand::random::<u32>()));
+        let spec = CertificateSpec {
+            domains: vec!["dns.example.test".to_string()],
+            paths: CertificatePaths {
+                cert: dir.join("fullchain.pem"),
+                key: dir.join("privkey.pem"),
+            },
+        };
+        let settings = AcmeSettings {
+            directory_url,
+            email: "user@example.com".to_string(),
+            eab: None,
+            preferred_chain: None,
+            renew_before: Duration::hours(1),
+            key_type: KeyType::EcdsaP256,
+            key_policy: KeyPolicy::Rotate,
+            account_path: dir.join("acme-account.json"),
+        };
+        let solver = ChallengeSolver::Http01(Http01Challenges::default());
+        let task = AcmeTask::new(vec![spec.clone()], settings, solver).unwrap();
+
+        task.write_fallbacks(None).await.unwrap();
+        assert!(task.run().await.unwrap(), "the fallback is replaced");
+        let info = CertificateInfo::load(&spec.paths.cert).await.unwrap();
+        assert!(!info.is_fallback());
+        assert_eq!(info.names, spec.domains);
+        assert!(!task.run().await.unwrap(), "a fresh certificate is kept");
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -1,38 +1,328 @@
-use std::net::SocketAddr;
-
-use tokio::process::{Child, Command};
-
-pub fn spawn_dnsdist(
-    tls_enabled: bool,
-    backend: SocketAddr,
-    port: u16,
-) -> Result<Child, anyhow::Error> {
-    let child = Command::new("dnsdist")
-        .env("TLS_ENABLED", tls_enabled.to_string())
-        .env("BACKEND", backend.to_string())
-        .env("PORT", port.to_string())
+use std::{net::SocketAddr, path::Path, sync::Arc, time::Duration};
+
+use anyhow::{bail, Context};
+use tokio::{
+    process::{Child, Command},
+    sync::{mpsc, oneshot, Mutex},
+};
+
+use crate::certs::write_private_file;
+use crate::dnsdist::{DnsdistConfig, DnsdistConsole};
+use crate::tasks::supervisor::{spawn_child, stop_child, wait_child};
+
+/// Where the rendered config is written, it holds the console key so only the owner can read it
+pub const CONFIG_FILE: &str = "./dnsdist.generated.conf";
+
+/// Where a new config is checked, before it replaces `CONFIG_FILE`
+const CANDIDATE_FILE: &str = "./dnsdist.generated.conf.new";
+
+/// Turns the output of `dnsdist --check-config` into its error messages, pointing at the lines
+/// of the config they are about
+fn describe_check_errors(output: &str, path: &str, config: &str) -> String {
+    let lines: Vec<&str> = config.lines().collect();
+    let prefix = format!("{path}:");
+
+    let mut errors = Vec::new();
+    for line in output.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let number = line.find(&prefix).and_then(|start| {
+            let rest = &line[start + prefix.len()..];
+            let (number, _) = rest.split_once(':')?;
+            number.parse::<usize>().ok()
+        });
+        let line = line.replace(&prefix, "line ");
+        match number.and_then(|n| Some((n, lines.get(n.checked_sub(1)?)?))) {
+            Some((n, config_line)) => {
+                errors.push(format!("{line}\n  {n} | {}", config_line.trim()));
+            }
+            None => errors.push(line),
+        }
+    }
+    errors.join("\n")
+}
+
+/// Renders the config to `path` and checks it with `dnsdist --check-config`, the lua errors are
+/// returned as the error
+pub async fn write_checked_config(
+    config: &DnsdistConfig,
+    path: &Path,
+) -> Result<(), anyhow::Error> {
+    let rendered = config.render();
+    write_private_file(path, rendered.as_bytes()).await?;
+
+    let output = Command::new("dnsdist")
+        .arg("--check-config")
+        .arg("--config")
+        .arg(path)
+        .output()
+        .await
+        .context("fail to run dnsdist --check-config")?;
+    if !output.status.success() {
+        let output = String::from_utf8_lossy(&output.stderr).into_owned()
+            + &String::from_utf8_lossy(&output.stdout);
+        let errors = describe_check_errors(&output, &path.display().to_string(), &rendered);
+        bail!("dnsdist rejected the config\n{errors}");
+    }
+
+    Ok(())
+}
+
+/// Checks the config, and only then replaces `CONFIG_FILE` with it. A config that does not pass
+/// leaves the current one in place.
+pub async fn install_config(config: &DnsdistConfig) -> Result<(), anyhow::Error> {
+    write_checked_config(config, Path::new(CANDIDATE_FILE)).await?;
+    tokio::fs::rename(CANDIDATE_FILE, CONFIG_FILE).await?;
+    Ok(())
+}
+
+/// How long a restarted dnsdist gets to answer on its console, before it is given up on
+const HANDOVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the previous dnsdist gets to finish its queries after a handover, before it is killed
+const STOP_GRACE: Duration = Duration::from_secs(5);
+
+fn dnsdist_command(config_file: &str) -> Command {
+    let mut command = Command::new("dnsdist");
+    command
         .arg("--supervised")
         .arg("--disable-syslog")
         .arg("--config")
-        .arg("dnsdist.conf")
-        .kill_on_drop(true)
-        .spawn()?;
+        .arg(config_file);
+    command
+}
 
-    Ok(child)
+/// Asks the running dnsdist to hand over to a new one with the config, the config it started
+/// with is sent back
+struct RestartRequest {
+    config: DnsdistConfig,
+    done: oneshot::Sender<Result<DnsdistConfig, anyhow::Error>>,
 }
 
-pub async fn run_dnsdist_reload_cert() -> Result<(), anyhow::Error> {
-    let res = Command::new("dnsdist")
-        .arg("-c")
-        .arg("127.0.0.1")
-        .arg("-k")
-        .arg("miQjUydO7fwUmSDS0hT+2pHC1VqT8vOjfexOyvHKcNA=")
-        .arg("-e")
-        .arg("reloadAllCertificates()")
-        .status()
-        .await?;
+/// Runs dnsdist with the installed `CONFIG_FILE`, and replaces it on restart requests. The new
+/// dnsdist starts while the previous one still serves, their listeners share the ports with
+/// reusePort. The console port cannot be shared, so the new dnsdist gets the other of the two
+/// console ports.
+pub struct DnsdistProcess {
+    console: DnsdistConsole,
+    console_addresses: [SocketAddr; 2],
+    requests: Mutex<mpsc::Receiver<RestartRequest>>,
+}
 
-    tracing::info!("dnsdist reload status: {res}");
+impl DnsdistProcess {
+    /// The console starts on its current address, the other console port is the next one
+    pub fn new(console: DnsdistConsole) -> Result<(Self, DnsdistRestarter), anyhow::Error> {
+        let address = console.address();
+        let port = address
+            .port()
+            .checked_add(1)
+            .context("console port is too high")?;
+        let (sender, receiver) = mpsc::channel(1);
+        let process = Self {
+            console,
+            console_addresses: [address, SocketAddr::new(address.ip(), port)],
+            requests: Mutex::new(receiver),
+        };
 
-    Ok(())
+        Ok((process, DnsdistRestarter { sender }))
+    }
+
+    /// Runs dnsdist until it exits
+    pub async fn run(&self) -> Result<(), anyhow::Error> {
+        let mut requests = self.requests.lock().await;
+        let mut child = spawn_child("dnsdist", dnsdist_command(CONFIG_FILE))?;
+        loop {
+            let request = tokio::select! {
+                res = wait_child("dnsdist", &mut child) => return res,
+                Some(request) = requests.recv() => request,
+            };
+
+            let res = match self.hand_over(request.config).await {
+                Ok((new_child, config)) => {
+                    let previous = std::mem::replace(&mut child, new_child);
+                    tokio::spawn(stop_child("previous dnsdist", previous, STOP_GRACE));
+                    Ok(config)
+                }
+                Err(err) => Err(err),
+            };
+            let _ = request.done.send(res);
+        }
+    }
+
+    async fn hand_over(
+        &self,
+        mut config: DnsdistConfig,
+    ) -> Result<(Child, DnsdistConfig), anyhow::Error> {
+        let address = match self.console.address() == self.console_addresses[0] {
+            true => self.console_addresses[1],
+            false => self.console_addresses[0],
+        };
+        if let Some(console) = config.console.as_mut() {
+            console.address = address;
+        }
+
+        write_checked_config(&config, Path::new(CANDIDATE_FILE)).await?;
+        let mut child = spawn_child("dnsdist", dnsdist_command(CANDIDATE_FILE))?;
+
+        let console = DnsdistConsole::new(address, self.console.key().to_string());
+        let started = tokio::time::timeout(HANDOVER_TIMEOUT, async {
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    bail!("the new dnsdist exited with {status}");
+                }
+                if console.run("showVersion()").await.is_ok() {
+                    return Ok(());
+                }
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+        })
+        .await;
+        match started {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => return Err(err),
+            Err(_) => {
+                bail!("the new dnsdist did not answer on its console in {HANDOVER_TIMEOUT:?}")
+            }
+        }
+
+        tokio::fs::rename(CANDIDATE_FILE, CONFIG_FILE).await?;
+        self.console.set_address(address);
+        Ok((child, config))
+    }
+}
+
+#[derive(Clone)]
+pub struct DnsdistRestarter {
+    sender: mpsc::Sender<RestartRequest>,
+}
+
+impl DnsdistRestarter {
+    /// Replaces the running dnsdist with one started with the config, and returns the config
+    /// it was started with
+    async fn restart(&self, config: DnsdistConfig) -> Result<DnsdistConfig, anyhow::Error> {
+        let (done, receiver) = oneshot::channel();
+        self.sender
+            .send(RestartRequest { config, done })
+            .await
+            .context("dnsdist is not running")?;
+        receiver.await.context("dnsdist is not running")?
+    }
+}
+
+/// How a reload was applied
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReloadOutcome {
+    Unchanged,
+    /// Applied through the console
+    Applied,
+    /// dnsdist was restarted
+    Restarted,
+}
+
+/// Holds the config of the running dnsdist, and moves dnsdist to new configs
+#[derive(Clone)]
+pub struct DnsdistReloader {
+    config: Arc<Mutex<DnsdistConfig>>,
+    /// Held for a whole reload, so `config` is only locked to be read or replaced
+    reloading: Arc<Mutex<()>>,
+    console: DnsdistConsole,
+    restarter: DnsdistRestarter,
+}
+
+impl DnsdistReloader {
+    pub fn new(
+        config: DnsdistConfig,
+        console: DnsdistConsole,
+        restarter: DnsdistRestarter,
+    ) -> Self {
+        Self {
+            config: Arc::new(Mutex::new(config)),
+            reloading: Arc::new(Mutex::new(())),
+            console,
+            restarter,
+        }
+    }
+
+    pub fn console(&self) -> &DnsdistConsole {
+        &self.console
+    }
+
+    pub async fn config(&self) -> DnsdistConfig {
+        self.config.lock().await.clone()
+    }
+
+    /// Applies the new config through the console when it can, and restarts dnsdist otherwise.
+    /// A config that fails the check is not applied. When the console fails part way, dnsdist
+    /// is restarted with the current config, as some of the commands may have been applied.
+    pub async fn reload(&self, new: DnsdistConfig) -> Result<ReloadOutcome, anyhow::Error> {
+        let _reloading = self.reloading.lock().await;
+        let current = self.config().await;
+        match current.reload_commands(&new) {
+            Some(commands) if commands.is_empty() => Ok(ReloadOutcome::Unchanged),
+            Some(commands) => {
+                // only installed once dnsdist applied it, so a dnsdist restarted later starts
+                // with the config that was running
+                write_checked_config(&new, Path::new(CANDIDATE_FILE)).await?;
+                if let Err(err) = self.console.run(&commands).await {
+                    tracing::warn!("dnsdist reload failed, restarting with the current config");
+                    let config = self
+                        .restarter
+                        .restart(current)
+                        .await
+                        .with_context(|| format!("fail to restore dnsdist after {err:#}"))?;
+                    *self.config.lock().await = config;
+                    return Err(err.context("dnsdist was restarted with the current config"));
+                }
+                tokio::fs::rename(CANDIDATE_FILE, CONFIG_FILE).await?;
+                *self.config.lock().await = new;
+                Ok(ReloadOutcome::Applied)
+            }
+            None => {
+                let config = self.restarter.restart(new).await?;
+                *self.config.lock().await = config;
+                Ok(ReloadOutcome::Restarted)
+            }
+        }
+    }
+}
+
+type ReloadReply = oneshot::Sender<Result<ReloadOutcome, anyhow::Error>>;
+
+/// Asks the reload task to re-read the settings and reload dnsdist with them
+#[derive(Clone)]
+pub struct ReloadTrigger {
+    sender: mpsc::Sender<ReloadReply>,
+}
+
+impl ReloadTrigger {
+    pub fn new() -> (Self, mpsc::Receiver<ReloadReply>) {
+        let (sender, receiver) = mpsc::channel(8);
+        (Self { sender }, receiver)
+    }
+
+    pub async fn reload(&self) -> Result<ReloadOutcome, anyhow::Error> {
+        let (reply, receiver) = oneshot::channel();
+        self.sender
+            .send(reply)
+            .await
+            .context("the reload task is not running")?;
+        receiver.await.context("the reload task is not running")?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::describe_check_errors;
+
+    #[test]
+    fn test_describe_check_errors() {
+        let config = "setServerPolicy(leastOutstanding)\nnewServer({ address='bad' })\n";
+        let output = "Fatal Lua error: ./dnsdist.generated.conf.new:2: Unable to convert presentation address 'bad'\n\nConfiguration check failed\n";
+
+        assert_eq!(
+            describe_check_errors(output, "./dnsdist.generated.conf.new", config),
+            "Fatal Lua error: line 2: Unable to convert presentation address 'bad'\n  \
+             2 | newServer({ address='bad' })\n\
+             Configuration check failed"
+        );
+    }
 }
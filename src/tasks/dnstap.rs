@@ -1,59 +1,126 @@
-use tokio::process::{Child, Command};
-
-// # dnstap -h
-// Usage: dnstap [OPTION]...
-//   -T value
-//     	write dnstap payloads to tcp/ip address
-//   -U value
-//     	write dnstap payloads to unix socket
-//   -a	append to the given file, do not overwrite. valid only when outputting a text or YAML file.
-//   -j	use verbose JSON output
-//   -l value
-//     	read dnstap payloads from tcp/ip
-//   -q	use quiet text output
-//   -r value
-//     	read dnstap payloads from file
-//   -t duration
-//     	I/O timeout for tcp/ip and unix domain sockets
-//   -u value
-//     	read dnstap payloads from unix socket
-//   -w string
-//     	write output to file
-//   -y	use verbose YAML output
-
-// Quiet text output format mnemonics:
-//     AQ: AUTH_QUERY
-//     AR: AUTH_RESPONSE
-//     RQ: RESOLVER_QUERY
-//     RR: RESOLVER_RESPONSE
-//     CQ: CLIENT_QUERY
-//     CR: CLIENT_RESPONSE
-//     FQ: FORWARDER_QUERY
-//     FR: FORWARDER_RESPONSE
-//     SQ: STUB_QUERY
-//     SR: STUB_RESPONSE
-//     TQ: TOOL_QUERY
-//     TR: TOOL_RESPONSE
-
-pub fn spawn_dnstap() -> Result<Child, anyhow::Error> {
-    let child = Command::new("dnstap")
-        .arg("-y")
-        .arg("-u")
-        .arg("dnstap.sock")
-        .arg("-a")
-        .arg("-w")
-        .arg("logs.yaml")
-        .kill_on_drop(true)
-        .spawn()?;
-
-    Ok(child)
+use std::{path::Path, time::Duration};
+
+use prost::Message;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+use crate::dnstap::{Dnstap, FrameStreamReader, Message as DnstapMessage, DNSTAP_CONTENT_TYPE};
+use crate::logs::UsageStats;
+
+pub const DNSTAP_SOCKET: &str = "./dnstap.sock";
+
+/// The most dnstap messages waiting for the logs consumer, which takes them every second. Once
+/// it is full the socket is not read, and dnsdist drops the messages it cannot send.
+pub const DNSTAP_CHANNEL_CAPACITY: usize = 100_000;
+
+/// The least time between two warnings about frames that fail to decode
+const DECODE_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Listens on the dnstap unix socket that dnsdist's FrameStreamUnixLogger connects to,
+/// and forwards every decoded dnstap message to the sender. Frames that fail to decode are
+/// counted as invalid logs in the usage stats. The connections are handled by tasks owned by the
+/// returned future, so they end with it when the listener is cancelled or restarted.
+pub async fn run_dnstap_listener(
+    path: impl AsRef<Path>,
+    sender: Sender<DnstapMessage>,
+    usage_stats: UsageStats,
+) -> Result<(), anyhow::Error> {
+    let path = path.as_ref();
+    if path.exists() {
+        tokio::fs::remove_file(path).await?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    let mut connections = JoinSet::new();
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            // reaps the connections that closed
+            Some(_) = connections.join_next() => continue,
+        };
+        tracing::info!("dnstap accepted connection");
+
+        let sender = sender.clone();
+        let usage_stats = usage_stats.clone();
+        connections.spawn(async move {
+            if let Err(err) = handle_dnstap_connection(stream, sender, usage_stats).await {
+                tracing::error!("dnstap connection. ERROR: {err}");
+            }
+            tracing::info!("dnstap connection closed");
+        });
+    }
 }
 
-pub async fn read_dnstap_logs() -> String {
-    let content = tokio::fs::read_to_string("./logs.yaml")
-        .await
-        .unwrap_or_default();
-    let _ = tokio::fs::write("./logs.yaml", "").await;
+async fn handle_dnstap_connection(
+    stream: UnixStream,
+    sender: Sender<DnstapMessage>,
+    usage_stats: UsageStats,
+) -> Result<(), anyhow::Error> {
+    let mut reader = FrameStreamReader::accept(stream, DNSTAP_CONTENT_TYPE).await?;
+
+    let mut decode_failures = 0;
+    let mut warned_at: Option<Instant> = None;
+    while let Some(frame) = reader.next_frame().await? {
+        let dnstap = match Dnstap::decode(frame.as_slice()) {
+            Ok(dnstap) => dnstap,
+            Err(err) => {
+                usage_stats.add_invalid_logs(1);
+                decode_failures += 1;
+                if !matches!(warned_at, Some(at) if at.elapsed() < DECODE_WARNING_INTERVAL) {
+                    tracing::warn!(
+                        "dnstap fail to decode {decode_failures} frames. LAST ERROR: {err}"
+                    );
+                    decode_failures = 0;
+                    warned_at = Some(Instant::now());
+                }
+                continue;
+            }
+        };
+
+        if let Some(message) = dnstap.message {
+            sender.send(message).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::AsyncReadExt;
+    use tokio::net::UnixStream;
+
+    use super::run_dnstap_listener;
+    use crate::logs::UsageStats;
+
+    #[tokio::test]
+    async fn test_connections_end_with_listener() {
+        let path = std::env::temp_dir().join(format!("{}-dnstap.sock", std::process::id()));
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let listener = tokio::spawn({
+            let path = path.clone();
+            async move { run_dnstap_listener(&path, sender, UsageStats::default()).await }
+        });
+
+        let mut stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        // the connection waits for the handshake until the listener is stopped
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        listener.abort();
 
-    content
+        let mut buf = [0; 1];
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .expect("the connection is closed with the listener");
+        assert_eq!(read.unwrap(), 0);
+        let _ = std::fs::remove_file(&path);
+    }
 }
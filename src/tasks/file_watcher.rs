@@ -0,0 +1,82 @@
+use std::{path::PathBuf, time::SystemTime};
+
+/// What is compared between polls, a rewrite of the same size within the mtime resolution is missed
+type FileState = Option<(SystemTime, u64)>;
+
+/// Polls files for changes. A change is only reported once the files have been
+/// the same for two polls in a row, so files that are written one after the other
+/// are picked up together.
+pub struct FileWatcher {
+    paths: Vec<PathBuf>,
+    loaded: Vec<FileState>,
+    last_seen: Vec<FileState>,
+}
+
+impl FileWatcher {
+    /// Starts from the current state of the files, which is assumed to be loaded already
+    pub async fn new(paths: Vec<PathBuf>) -> Self {
+        let loaded = read_states(&paths).await;
+        let last_seen = loaded.clone();
+        Self {
+            paths,
+            loaded,
+            last_seen,
+        }
+    }
+
+    /// Returns true if the files changed since they were loaded, and have settled since the last poll
+    pub async fn poll(&mut self) -> bool {
+        let states = read_states(&self.paths).await;
+        let settled = states == self.last_seen;
+        self.last_seen = states;
+
+        if settled && self.last_seen != self.loaded {
+            self.loaded = self.last_seen.clone();
+            return true;
+        }
+
+        false
+    }
+}
+
+async fn read_states(paths: &[PathBuf]) -> Vec<FileState> {
+    let mut states = Vec::new();
+    for path in paths.iter() {
+        let state = match tokio::fs::metadata(path).await {
+            Ok(meta) => meta.modified().ok().map(|mtime| (mtime, meta.len())),
+            Err(_) => None,
+        };
+        states.push(state);
+    }
+    states
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileWatcher;
+
+    #[tokio::test]
+    async fn test_file_watcher() {
+        let dir = std::env::temp_dir().join(format!("watch-test-{}", rand::random::<u32>()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let cert = dir.join("fullchain.pem");
+        tokio::fs::write(&cert, "first").await.unwrap();
+
+        let mut watcher = FileWatcher::new(vec![cert.clone()]).await;
+        assert!(!watcher.poll().await);
+
+        tokio::fs::write(&cert, "second write").await.unwrap();
+        assert!(
+            !watcher.poll().await,
+            "a change is reported once it settles"
+        );
+        assert!(watcher.poll().await);
+        assert!(!watcher.poll().await, "a change is only reported once");
+
+        tokio::fs::remove_file(&cert).await.unwrap();
+        assert!(!watcher.poll().await);
+        assert!(watcher.poll().await);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -1,3 +1,5 @@
-pub mod certbot;
+pub mod acme;
 pub mod dnsdist;
 pub mod dnstap;
+pub mod file_watcher;
+pub mod supervisor;
@@ -0,0 +1,319 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    future::Future,
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{Child, Command},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+
+/// When a supervised process is started again after it exits
+#[derive(ValueEnum, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// After every exit
+    Always,
+    /// After it fails
+    OnFailure,
+    /// Never, its exit is final
+    Never,
+}
+
+/// How fast, and how often, a supervised process is restarted
+#[derive(Debug, Clone)]
+pub struct RestartSettings {
+    pub policy: RestartPolicy,
+    /// The delay before the first restart, doubled after every following one
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Gives up once there were `max_restarts` restarts within `window`
+    pub max_restarts: usize,
+    pub window: Duration,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct ProcessExit {
+    pub time: DateTime<Utc>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct ProcessStatus {
+    pub name: String,
+    pub policy: RestartPolicy,
+    pub running: bool,
+    pub started_at: Option<DateTime<Utc>>,
+    pub restarts: usize,
+    pub last_exit: Option<ProcessExit>,
+}
+
+/// Tracks the supervised processes, for the status endpoint
+#[derive(Debug, Clone, Default)]
+pub struct SupervisorStatus {
+    processes: Arc<Mutex<BTreeMap<String, ProcessStatus>>>,
+}
+
+impl SupervisorStatus {
+    pub fn get(&self) -> Vec<ProcessStatus> {
+        self.processes.lock().unwrap().values().cloned().collect()
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut ProcessStatus)) {
+        if let Some(status) = self.processes.lock().unwrap().get_mut(name) {
+            f(status);
+        }
+    }
+}
+
+/// Restarts a process according to its settings, until it is given up on or cancelled
+pub struct Supervisor {
+    name: String,
+    settings: RestartSettings,
+    status: SupervisorStatus,
+}
+
+impl Supervisor {
+    pub fn new(name: &str, settings: RestartSettings, status: SupervisorStatus) -> Self {
+        status.processes.lock().unwrap().insert(
+            name.to_string(),
+            ProcessStatus {
+                name: name.to_string(),
+                policy: settings.policy,
+                running: false,
+                started_at: None,
+                restarts: 0,
+                last_exit: None,
+            },
+        );
+
+        Self {
+            name: name.to_string(),
+            settings,
+            status,
+        }
+    }
+
+    /// Runs the futures made by `start` one after the other. Returns once the policy does not
+    /// restart the process anymore, with its last error, or once the token is cancelled.
+    pub async fn run<F, Fut>(
+        &self,
+        token: &CancellationToken,
+        mut start: F,
+    ) -> Result<(), anyhow::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(), anyhow::Error>>,
+    {
+        let name = &self.name;
+        let settings = &self.settings;
+        let mut restarts: VecDeque<Instant> = VecDeque::new();
+        let mut backoff = settings.initial_backoff;
+
+        loop {
+            let started_at = Instant::now();
+            self.status.update(name, |s| {
+                s.running = true;
+                s.started_at = Some(Utc::now());
+            });
+
+            let res = tokio::select! {
+                _ = token.cancelled() => {
+                    self.status.update(name, |s| s.running = false);
+                    return Ok(());
+                },
+                res = start() => res,
+            };
+
+            self.status.update(name, |s| {
+                s.running = false;
+                s.last_exit = Some(ProcessExit {
+                    time: Utc::now(),
+                    success: res.is_ok(),
+                    error: res.as_ref().err().map(|err| format!("{err:#}")),
+                });
+            });
+            match &res {
+                Ok(()) => tracing::warn!("{name} exited"),
+                Err(err) => tracing::error!("{name} exited. ERROR: {err:#}"),
+            }
+
+            let restart = match settings.policy {
+                RestartPolicy::Always => true,
+                RestartPolicy::OnFailure => res.is_err(),
+                RestartPolicy::Never => false,
+            };
+            if !restart {
+                return res;
+            }
+
+            let now = Instant::now();
+            while restarts
+                .front()
+                .is_some_and(|t| now.duration_since(*t) > settings.window)
+            {
+                restarts.pop_front();
+            }
+            if restarts.len() >= settings.max_restarts {
+                let err = res.err().unwrap_or_else(|| anyhow!("{name} exited"));
+                return Err(err.context(format!(
+                    "{name} restarted {} times within {:?}, giving up",
+                    restarts.len(),
+                    settings.window
+                )));
+            }
+
+            // a process that ran for a while is not crash looping, so it restarts quickly again
+            if now.duration_since(started_at) > settings.window {
+                backoff = settings.initial_backoff;
+            }
+            tracing::info!("{name} restarting in {backoff:?}");
+            tokio::select! {
+                _ = token.cancelled() => return Ok(()),
+                _ = tokio::time::sleep(backoff) => {},
+            }
+            backoff = (backoff * 2).min(settings.max_backoff);
+
+            restarts.push_back(Instant::now());
+            self.status.update(name, |s| s.restarts += 1);
+        }
+    }
+}
+
+async fn forward_lines(name: String, output: impl AsyncRead + Unpin, is_stderr: bool) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match is_stderr {
+            true => tracing::warn!("{name}: {line}"),
+            false => tracing::info!("{name}: {line}"),
+        }
+    }
+}
+
+/// Starts a child process that logs its stdout and stderr lines, and is killed when dropped
+pub fn spawn_child(name: &str, mut command: Command) -> Result<Child, anyhow::Error> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(forward_lines(name.to_string(), stdout, false));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(forward_lines(name.to_string(), stderr, true));
+    }
+
+    Ok(child)
+}
+
+/// Asks a child process to exit with SIGTERM, and kills it when it is still running after `grace`
+pub async fn stop_child(name: &str, mut child: Child, grace: Duration) {
+    if let Some(pid) = child.id() {
+        // SAFETY: kill only sends a signal, and the pid is not reused as the child is not reaped yet
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+    }
+
+    match tokio::time::timeout(grace, child.wait()).await {
+        Ok(Ok(status)) => tracing::info!("{name} stopped with {status}"),
+        Ok(Err(err)) => tracing::warn!("{name} stopping. ERROR: {err}"),
+        Err(_) => {
+            tracing::warn!("{name} did not stop within {grace:?}, killing it");
+            if let Err(err) = child.kill().await {
+                tracing::warn!("{name} killing. ERROR: {err}");
+            }
+        }
+    }
+}
+
+/// Waits for a child process to exit, an unsuccessful exit status is returned as an error
+pub async fn wait_child(name: &str, child: &mut Child) -> Result<(), anyhow::Error> {
+    let status = child.wait().await?;
+    if !status.success() {
+        bail!("{name} exited with {status}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::process::Command;
+    use tokio_util::sync::CancellationToken;
+
+    use super::{
+        spawn_child, stop_child, wait_child, RestartPolicy, RestartSettings, Supervisor,
+        SupervisorStatus,
+    };
+
+    #[tokio::test]
+    async fn test_supervisor() {
+        let settings = RestartSettings {
+            policy: RestartPolicy::OnFailure,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            max_restarts: 2,
+            window: Duration::from_secs(60),
+        };
+        let status = SupervisorStatus::default();
+        let token = CancellationToken::new();
+
+        let supervisor = Supervisor::new("failing", settings.clone(), status.clone());
+        let res = supervisor
+            .run(&token, || {
+                let mut command = Command::new("sh");
+                command.arg("-c").arg("echo starting; exit 3");
+                async move {
+                    let mut child = spawn_child("failing", command)?;
+                    wait_child("failing", &mut child).await
+                }
+            })
+            .await;
+        assert!(res.is_err());
+
+        let supervisor = Supervisor::new("exiting", settings, status.clone());
+        let res = supervisor.run(&token, || async { Ok(()) }).await;
+        assert!(res.is_ok());
+
+        let processes = status.get();
+        assert_eq!(processes[0].name, "exiting");
+        assert_eq!(processes[0].restarts, 0);
+        assert_eq!(processes[1].name, "failing");
+        assert_eq!(processes[1].restarts, 2);
+        assert!(!processes[1].running);
+        let last_exit = processes[1].last_exit.as_ref().unwrap();
+        assert!(!last_exit.success);
+        assert!(last_exit.error.as_ref().unwrap().contains("exit status: 3"));
+    }
+
+    #[tokio::test]
+    async fn test_stop_child() {
+        let grace = Duration::from_secs(5);
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("sleep 10 & wait");
+        let child = spawn_child("stopping", command).unwrap();
+        let started = tokio::time::Instant::now();
+        stop_child("stopping", child, grace).await;
+        assert!(started.elapsed() < grace, "stopped by SIGTERM");
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("trap '' TERM; sleep 10 & wait");
+        let child = spawn_child("ignoring", command).unwrap();
+        let started = tokio::time::Instant::now();
+        stop_child("ignoring", child, Duration::from_millis(200)).await;
+        assert!(started.elapsed() < grace, "killed after the grace period");
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    io::BufReader,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+
+use crate::certs::{CertificateInfo, CertificatePaths};
+
+/// Picks the certificate whose names match the SNI of the client hello,
+/// falling back to the first certificate when nothing matches
+#[derive(Debug)]
+pub struct SniCertResolver {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    default: Arc<CertifiedKey>,
+}
+
+impl SniCertResolver {
+    /// Loads the certificates in order, the first one becomes the default
+    pub async fn load(paths: &[CertificatePaths]) -> Result<Self, anyhow::Error> {
+        let mut by_name = HashMap::new();
+        let mut default = None;
+
+        for paths in paths.iter() {
+            let (names, key) = load_certified_key(paths)
+                .await
+                .with_context(|| format!("fail to load {}", paths.cert.display()))?;
+
+            for name in names {
+                by_name.entry(name).or_insert_with(|| key.clone());
+            }
+            default.get_or_insert(key);
+        }
+
+        let default = default.context("no tls certificate configured")?;
+        Ok(Self { by_name, default })
+    }
+
+    fn find(&self, server_name: &str) -> Option<Arc<CertifiedKey>> {
+        let server_name = server_name.to_ascii_lowercase();
+        if let Some(key) = self.by_name.get(&server_name) {
+            return Some(key.clone());
+        }
+
+        // a wildcard name only covers a single label
+        let (_, parent) = server_name.split_once('.')?;
+        self.by_name.get(&format!("*.{parent}")).cloned()
+    }
+}
+
+impl ResolvesServerCert for SniCertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let key = client_hello.server_name().and_then(|name| self.find(name));
+        Some(key.unwrap_or_else(|| self.default.clone()))
+    }
+}
+
+async fn load_certified_key(
+    paths: &CertificatePaths,
+) -> Result<(Vec<String>, Arc<CertifiedKey>), anyhow::Error> {
+    let cert_pem = tokio::fs::read(&paths.cert).await?;
+    let key_pem = tokio::fs::read(&paths.key).await?;
+
+    let names = CertificateInfo::from_pem(&cert_pem)?.names;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert_pem.as_slice()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key_pem.as_slice()))?
+        .with_context(|| format!("no private key in {}", paths.key.display()))?;
+    let key = rustls::crypto::aws_lc_rs::sign::any_supported_type(&key)?;
+
+    let names = names.iter().map(|n| n.to_ascii_lowercase()).collect();
+    Ok((names, Arc::new(CertifiedKey::new(certs, key))))
+}
+
+/// Builds the https server config, selecting between the certificates by SNI
+pub async fn load_server_config(
+    paths: &[CertificatePaths],
+) -> Result<Arc<ServerConfig>, anyhow::Error> {
+    let resolver = SniCertResolver::load(paths).await?;
+
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(resolver));
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RenewalAttempt {
+    pub time: DateTime<Utc>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct CertificateStatus {
+    pub path: String,
+    #[serde(flatten)]
+    pub info: Option<CertificateInfo>,
+    pub days_remaining: Option<i64>,
+    pub fallback: bool,
+    pub error: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug, Clone, Default)]
+pub struct TlsStatusOutput {
+    pub enabled: bool,
+    pub certificates: Vec<CertificateStatus>,
+    pub last_renewal: Option<RenewalAttempt>,
+    pub renewal_failures: usize,
+    pub next_renewal: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default)]
+struct RenewalState {
+    last_attempt: Option<RenewalAttempt>,
+    failures: usize,
+    next_renewal: Option<DateTime<Utc>>,
+}
+
+/// Tracks the served certificates and the renewal task, for the status and metrics endpoints
+#[derive(Debug, Clone, Default)]
+pub struct TlsStatus {
+    paths: Arc<Vec<CertificatePaths>>,
+    renewal: Arc<Mutex<RenewalState>>,
+}
+
+impl TlsStatus {
+    pub fn new(paths: Vec<CertificatePaths>) -> Self {
+        Self {
+            paths: Arc::new(paths),
+            renewal: Default::default(),
+        }
+    }
+
+    pub fn record_attempt<T>(&self, res: &Result<T, anyhow::Error>) {
+        let mut renewal = self.renewal.lock().unwrap();
+        if res.is_err() {
+            renewal.failures += 1;
+        }
+        renewal.last_attempt = Some(RenewalAttempt {
+            time: Utc::now(),
+            success: res.is_ok(),
+            error: res.as_ref().err().map(|err| format!("{err:#}")),
+        });
+    }
+
+    pub fn set_next_renewal(&self, time: DateTime<Utc>) {
+        self.renewal.lock().unwrap().next_renewal = Some(time);
+    }
+
+    /// Reads the certificates from disk, so the output matches what dnsdist loads on reload
+    pub async fn get(&self) -> TlsStatusOutput {
+        let mut certificates = Vec::new();
+        for paths in self.paths.iter() {
+            let path = paths.cert.display().to_string();
+            let status = match CertificateInfo::load(&paths.cert).await {
+                Ok(info) => CertificateStatus {
+                    path,
+                    days_remaining: Some(info.days_remaining()),
+                    fallback: info.is_fallback(),
+                    info: Some(info),
+                    error: None,
+                },
+                Err(err) => CertificateStatus {
+                    path,
+                    info: None,
+                    days_remaining: None,
+                    fallback: false,
+                    error: Some(format!("{err:#}")),
+                },
+            };
+            certificates.push(status);
+        }
+
+        let renewal = self.renewal.lock().unwrap();
+        TlsStatusOutput {
+            enabled: !self.paths.is_empty(),
+            certificates,
+            last_renewal: renewal.last_attempt.clone(),
+            renewal_failures: renewal.failures,
+            next_renewal: renewal.next_renewal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{CertificateParams, KeyPair};
+
+    use super::SniCertResolver;
+    use crate::certs::{write_certificate, CertificatePaths};
+
+    async fn write_self_signed(dir: &std::path::Path, names: &[&str]) -> CertificatePaths {
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        let key_pair = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(names.clone())
+            .unwrap()
+            .self_signed(&key_pair)
+            .unwrap();
+
+        let dir = dir.join(&names[0]);
+        let paths = CertificatePaths {
+            cert: dir.join("fullchain.pem"),
+            key: dir.join("privkey.pem"),
+        };
+        write_certificate(&paths, &cert.pem(), &key_pair.serialize_pem())
+            .await
+            .unwrap();
+        paths
+    }
+
+    #[tokio::test]
+    async fn test_sni_cert_resolver() {
+        let dir = std::env::temp_dir().join(format!("sni-test-{}", rand::random::<u32>()));
+        let first = write_self_signed(&dir, &["dns.example.com"]).await;
+        let second = write_self_signed(&dir, &["*.example.net", "example.net"]).await;
+
+        let resolver = SniCertResolver::load(&[first, second]).await.unwrap();
+        let default = resolver.default.clone();
+
+        let wildcard = resolver.find("SG.example.net").unwrap();
+        assert!(!std::sync::Arc::ptr_eq(&wildcard, &default));
+        assert!(std::sync::Arc::ptr_eq(
+            &resolver.find("example.net").unwrap(),
+            &wildcard
+        ));
+        assert!(std::sync::Arc::ptr_eq(
+            &resolver.find("dns.example.com").unwrap(),
+            &default
+        ));
+        assert!(resolver.find("a.b.example.net").is_none());
+        assert!(resolver.find("other.example.org").is_none());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}